@@ -0,0 +1,140 @@
+//! Pluggable on-disk formats for a serialized [`Tree`].
+//!
+//! `Tree::save_to_file`/`Tree::load_from_file` always assumed one bincode
+//! format, read into memory via `std::fs::read`. [`DbBackend`] abstracts
+//! that save/load pair behind a trait so a second format can coexist:
+//! [`MmapBackend`] writes byte-for-byte the same bincode format but loads it
+//! through a memory map instead of a heap-allocated read buffer, avoiding the
+//! one-time cost of copying the whole file into a `Vec<u8>` before
+//! deserializing it. It does *not* avoid materializing the deserialized
+//! `Tree` in RAM — `bincode::deserialize` still walks the mapped bytes and
+//! allocates every field up front, so peak memory is unchanged from
+//! `BincodeBackend`. [`crate::kv_index::KvKmerIndex`] keeps posting lists
+//! on disk and pages them in per lookup, but classify mode still loads the
+//! whole `Tree` next to it. `raxtax index convert-db`
+//! converts a database from one backend to another.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use memmap2::Mmap;
+
+use crate::tree::Tree;
+
+/// A driver for one serialized on-disk representation of a reference
+/// [`Tree`], analogous to [`crate::tree::KmerIndex`] for k-mer posting
+/// lists, but covering the whole database.
+pub trait DbBackend {
+    /// Short identifier used by `--from`/`--to` on `raxtax index convert-db`.
+    fn name(&self) -> &'static str;
+    fn save(&self, tree: &Tree, path: &Path) -> Result<()>;
+    fn load(&self, path: &Path) -> Result<Tree>;
+}
+
+/// The original format: `Tree::save_to_file`/`Tree::load_from_file`, which
+/// read the whole file into a `Vec<u8>` before bincode-deserializing it.
+pub struct BincodeBackend;
+
+impl DbBackend for BincodeBackend {
+    fn name(&self) -> &'static str {
+        "bincode"
+    }
+
+    fn save(&self, tree: &Tree, path: &Path) -> Result<()> {
+        tree.save_to_file(Box::new(BufWriter::new(File::create(path)?)))
+    }
+
+    fn load(&self, path: &Path) -> Result<Tree> {
+        Tree::load_from_file(&path.to_path_buf())
+    }
+}
+
+/// Byte-for-byte the same format as [`BincodeBackend`]; only `load` differs,
+/// mapping the file into memory and deserializing straight from the mapped
+/// pages instead of reading it into a heap buffer first. This saves the
+/// single extra `Vec<u8>` copy of the file that `BincodeBackend` makes, but
+/// `bincode::deserialize` still materializes the entire `Tree` in RAM
+/// afterwards — it is not a lazy or zero-copy load, and peak memory while
+/// loading is the same as `BincodeBackend`'s. Individual fields aren't
+/// deserialized lazily, so this does not help a database that's larger
+/// than available RAM; it only helps avoid one redundant file-sized
+/// allocation.
+pub struct MmapBackend;
+
+impl DbBackend for MmapBackend {
+    fn name(&self) -> &'static str {
+        "mmap"
+    }
+
+    fn save(&self, tree: &Tree, path: &Path) -> Result<()> {
+        BincodeBackend.save(tree, path)
+    }
+
+    fn load(&self, path: &Path) -> Result<Tree> {
+        log::warn!(
+            "'mmap' only avoids one file-sized read buffer; it still materializes the whole \
+             Tree in RAM like 'bincode' does."
+        );
+        let file = File::open(path)?;
+        // Safety: the mapping is only read from, and is dropped (along with
+        // the backing file handle) before this function returns, so no
+        // other process truncating the file can invalidate a borrow we
+        // still hold.
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(bincode::deserialize(&mmap)?)
+    }
+}
+
+/// Resolves a `--from`/`--to` backend name from `raxtax index convert-db`.
+pub fn by_name(name: &str) -> Result<Box<dyn DbBackend>> {
+    match name {
+        "bincode" => Ok(Box::new(BincodeBackend)),
+        "mmap" => Ok(Box::new(MmapBackend)),
+        other => bail!("Unknown database backend '{other}', expected 'bincode' or 'mmap'"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{by_name, BincodeBackend, DbBackend, MmapBackend};
+
+    fn build_tree() -> crate::tree::Tree {
+        let fasta_str = r">a;tax=p:P1,c:C1,o:O1,f:F1,g:G1,s:S1;
+AAACCCTTTGGGA
+>b;tax=p:P1,c:C1,o:O1,f:F1,g:G1,s:S2;
+ATACGCTTTGGGA";
+        crate::parser::parse_reference_fasta_str(
+            fasta_str,
+            8,
+            1,
+            false,
+            None,
+            crate::utils::DEFAULT_MAX_AMBIGUITY,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_mmap_backend_round_trips_same_bytes_as_bincode() {
+        let tree = build_tree();
+        let tmp = std::env::temp_dir().join(format!(
+            "raxtax_db_backend_test_{}.bin",
+            std::process::id()
+        ));
+        MmapBackend.save(&tree, &tmp).unwrap();
+        let loaded = MmapBackend.load(&tmp).unwrap();
+        assert_eq!(tree, loaded);
+        let loaded_via_bincode = BincodeBackend.load(&tmp).unwrap();
+        assert_eq!(tree, loaded_via_bincode);
+        std::fs::remove_file(&tmp).unwrap();
+    }
+
+    #[test]
+    fn test_by_name_rejects_unknown_backend() {
+        assert!(by_name("bincode").is_ok());
+        assert!(by_name("mmap").is_ok());
+        assert!(by_name("zstd").is_err());
+    }
+}
@@ -0,0 +1,141 @@
+//! LZ77-style delta encoding of a byte buffer against a static dictionary.
+//!
+//! Unlike classic streaming LZ77, the "window" here never slides and never
+//! evicts: the dictionary is the entire `base` buffer, and copy operations
+//! may reference any offset into it. This fits the incremental reference
+//! index use case in [`crate::index`], where `base` is a previously
+//! serialized [`crate::tree::Tree`] and `target` is the newly serialized
+//! tree after a handful of reference sequences were added — the two share
+//! almost all of their bytes, so copies dominate and literals stay rare.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Byte length of the prefix used to index `base` for candidate matches.
+const MIN_MATCH_LEN: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum DeltaOp {
+    /// Copy `len` bytes from `base[offset..offset + len]`.
+    Copy { offset: u64, len: u64 },
+    /// Bytes that don't appear (long enough) anywhere in `base`.
+    Literal(Vec<u8>),
+}
+
+/// Encodes `target` as a sequence of copy/literal operations against `base`.
+pub fn encode_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut positions: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    if base.len() >= MIN_MATCH_LEN {
+        for i in 0..=base.len() - MIN_MATCH_LEN {
+            positions
+                .entry(&base[i..i + MIN_MATCH_LEN])
+                .or_default()
+                .push(i);
+        }
+    }
+
+    let mut ops: Vec<DeltaOp> = Vec::new();
+    let mut literal_run: Vec<u8> = Vec::new();
+    let mut i = 0;
+    while i < target.len() {
+        let best_match = if i + MIN_MATCH_LEN <= target.len() {
+            positions
+                .get(&target[i..i + MIN_MATCH_LEN])
+                .and_then(|candidates| {
+                    candidates
+                        .iter()
+                        .map(|&base_pos| {
+                            let len = base[base_pos..]
+                                .iter()
+                                .zip(&target[i..])
+                                .take_while(|(b, t)| b == t)
+                                .count();
+                            (base_pos, len)
+                        })
+                        .max_by_key(|&(_, len)| len)
+                })
+        } else {
+            None
+        };
+        match best_match {
+            Some((base_pos, len)) => {
+                if !literal_run.is_empty() {
+                    ops.push(DeltaOp::Literal(std::mem::take(&mut literal_run)));
+                }
+                ops.push(DeltaOp::Copy {
+                    offset: base_pos as u64,
+                    len: len as u64,
+                });
+                i += len;
+            }
+            None => {
+                literal_run.push(target[i]);
+                i += 1;
+            }
+        }
+    }
+    if !literal_run.is_empty() {
+        ops.push(DeltaOp::Literal(literal_run));
+    }
+    bincode::serialize(&ops).expect("in-memory delta op list is always serializable")
+}
+
+/// Reconstructs the original `target` bytes by replaying a patch (as
+/// produced by [`encode_delta`]) against `base`.
+pub fn apply_delta(base: &[u8], patch: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let ops: Vec<DeltaOp> = bincode::deserialize(patch)?;
+    let mut target = Vec::new();
+    for op in ops {
+        match op {
+            DeltaOp::Copy { offset, len } => {
+                let (offset, len) = (offset as usize, len as usize);
+                anyhow::ensure!(
+                    offset + len <= base.len(),
+                    "Corrupt patch: copy op references bytes outside of the base buffer"
+                );
+                target.extend_from_slice(&base[offset..offset + len]);
+            }
+            DeltaOp::Literal(bytes) => target.extend_from_slice(&bytes),
+        }
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_delta, encode_delta};
+
+    #[test]
+    fn test_round_trip_with_shared_prefix_and_suffix() {
+        let base = b"the quick brown fox jumps over the lazy dog".to_vec();
+        let target = b"the quick brown fox leaps over the lazy dog and runs away".to_vec();
+        let patch = encode_delta(&base, &target);
+        assert_eq!(apply_delta(&base, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn test_round_trip_with_empty_base() {
+        let base: Vec<u8> = Vec::new();
+        let target = b"entirely new content".to_vec();
+        let patch = encode_delta(&base, &target);
+        assert_eq!(apply_delta(&base, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn test_round_trip_with_identical_buffers() {
+        let base = b"no changes at all, just a long repeated buffer".to_vec();
+        let patch = encode_delta(&base, &base);
+        assert_eq!(apply_delta(&base, &patch).unwrap(), base);
+    }
+
+    #[test]
+    fn test_apply_rejects_out_of_bounds_copy() {
+        let base = b"short base".to_vec();
+        let bogus_patch = bincode::serialize(&vec![super::DeltaOp::Copy {
+            offset: 0,
+            len: 1000,
+        }])
+        .unwrap();
+        assert!(apply_delta(&base, &bogus_patch).is_err());
+    }
+}
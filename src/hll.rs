@@ -0,0 +1,117 @@
+//! HyperLogLog cardinality estimator, used as an optional lower-memory
+//! stand-in for exact per-reference k-mer intersection counting. Each
+//! reference keeps one [`HyperLogLog`] instead of its full k-mer set;
+//! intersections are recovered from cardinality estimates via
+//! inclusion-exclusion. Relative error is about `1.04 / sqrt(m)` with
+//! `m = 2^p` registers, so small intersections are the least reliable.
+
+use serde::{Deserialize, Serialize};
+
+pub const MIN_HLL_PRECISION: usize = 4;
+pub const MAX_HLL_PRECISION: usize = 16;
+pub const DEFAULT_HLL_PRECISION: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+    p: usize,
+}
+
+impl HyperLogLog {
+    pub fn new(p: usize) -> Self {
+        Self {
+            registers: vec![0_u8; 1_usize << p],
+            p,
+        }
+    }
+
+    pub fn insert_hash(&mut self, hash: u64) {
+        let remaining_bits = 64 - self.p as u32;
+        let idx = (hash >> remaining_bits) as usize;
+        let w = hash & ((1_u64 << remaining_bits) - 1);
+        let shifted = w << self.p;
+        let rank = if shifted == 0 {
+            (remaining_bits + 1) as u8
+        } else {
+            (shifted.leading_zeros() + 1) as u8
+        };
+        if rank > self.registers[idx] {
+            self.registers[idx] = rank;
+        }
+    }
+
+    fn alpha_m(m: usize) -> f64 {
+        match m {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m as f64),
+        }
+    }
+
+    /// Harmonic-mean cardinality estimate with the standard small-range and
+    /// large-range corrections.
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len();
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&r| 2_f64.powi(-i32::from(r)))
+            .sum();
+        let raw_estimate = Self::alpha_m(m) * (m * m) as f64 / sum;
+        let zero_registers = self.registers.iter().filter(|&&r| r == 0).count();
+        if raw_estimate <= 2.5 * m as f64 && zero_registers > 0 {
+            m as f64 * (m as f64 / zero_registers as f64).ln()
+        } else if raw_estimate <= (1_u64 << 32) as f64 / 30.0 {
+            raw_estimate
+        } else {
+            -(2_f64.powi(32)) * (1.0 - raw_estimate / 2_f64.powi(32)).ln()
+        }
+    }
+
+    /// Register-wise max, i.e. the HLL of the union of the two sketched sets.
+    pub fn union(&self, other: &Self) -> Self {
+        debug_assert_eq!(self.p, other.p);
+        Self {
+            registers: self
+                .registers
+                .iter()
+                .zip(&other.registers)
+                .map(|(&a, &b)| a.max(b))
+                .collect(),
+            p: self.p,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hll_of(values: &[u32], p: usize) -> HyperLogLog {
+        let mut hll = HyperLogLog::new(p);
+        for &v in values {
+            hll.insert_hash(crate::utils::hash_kmer(v));
+        }
+        hll
+    }
+
+    #[test]
+    fn test_estimate_within_error_bound() {
+        let values = (0..5000_u32).collect::<Vec<_>>();
+        let hll = hll_of(&values, 12);
+        let estimate = hll.estimate();
+        let relative_error = (estimate - values.len() as f64).abs() / values.len() as f64;
+        assert!(relative_error < 0.1, "relative error was {relative_error}");
+    }
+
+    #[test]
+    fn test_union_estimates_combined_cardinality() {
+        let a = hll_of(&(0..2000_u32).collect::<Vec<_>>(), 12);
+        let b = hll_of(&(1000..3000_u32).collect::<Vec<_>>(), 12);
+        let union = a.union(&b);
+        let estimate = union.estimate();
+        let relative_error = (estimate - 3000.0).abs() / 3000.0;
+        assert!(relative_error < 0.15, "relative error was {relative_error}");
+    }
+}
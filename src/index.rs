@@ -0,0 +1,213 @@
+//! `raxtax index` subcommand: incremental updates to a binary reference
+//! index. Rebuilding the whole k-mer index from scratch is wasteful for
+//! curated databases that only grow by a handful of sequences at a time, so
+//! `diff` builds the updated index once and stores it as a delta against
+//! the base index's bytes (see [`crate::delta`]), and `apply` replays that
+//! delta to reconstruct the updated index on another machine without
+//! shipping the full binary again.
+
+use std::io::Read;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::{Args, Subcommand};
+
+use crate::{
+    db_backend, delta,
+    io::{Checkpoint, FileFingerprint},
+    kv_index, parser,
+    tree::Tree,
+};
+
+#[derive(Subcommand)]
+pub enum IndexCommand {
+    /// Build a delta patch for an index updated with additional reference sequences
+    Diff(DiffArgs),
+    /// Reconstruct an updated index by applying a delta patch to its base index
+    Apply(ApplyArgs),
+    /// Build an on-disk, memory-mapped k-mer posting-list index alongside a binary database
+    BuildKv(BuildKvArgs),
+    /// Convert a binary reference index between on-disk database backends
+    ConvertDb(ConvertDbArgs),
+}
+
+#[derive(Args)]
+pub struct BuildKvArgs {
+    /// Path to the binary reference index to build a KV sidecar for
+    #[arg(long)]
+    pub database: PathBuf,
+    /// Where to write the KV sidecar file
+    #[arg(long)]
+    pub output: PathBuf,
+}
+
+#[derive(Args)]
+pub struct DiffArgs {
+    /// Path to the existing binary reference index
+    #[arg(long)]
+    pub base: PathBuf,
+    /// Path to a FASTA file with the additional reference sequences
+    #[arg(long)]
+    pub add: PathBuf,
+    /// Where to write the delta patch
+    #[arg(long)]
+    pub delta: PathBuf,
+}
+
+#[derive(Args)]
+pub struct ApplyArgs {
+    /// Path to the base binary reference index the patch was built against
+    #[arg(long)]
+    pub base: PathBuf,
+    /// Path to the delta patch produced by `raxtax index diff`
+    #[arg(long)]
+    pub delta: PathBuf,
+    /// Where to write the reconstructed binary reference index
+    #[arg(long)]
+    pub output: PathBuf,
+}
+
+#[derive(Args)]
+pub struct ConvertDbArgs {
+    /// Path to the input binary reference index
+    #[arg(long)]
+    pub input: PathBuf,
+    /// Backend the input file was written with: "bincode" or "mmap"
+    #[arg(long, default_value = "bincode")]
+    pub from: String,
+    /// Where to write the converted binary reference index
+    #[arg(long)]
+    pub output: PathBuf,
+    /// Backend to write the output file with: "bincode" or "mmap". Note
+    /// that "mmap" only saves the initial read-into-buffer copy; it still
+    /// materializes the whole database in RAM on load like "bincode" does.
+    #[arg(long)]
+    pub to: String,
+    /// Checkpoint file to repoint at the converted database, so a later
+    /// classify run against it picks up the new file without rebuilding
+    #[arg(long)]
+    pub checkpoint: Option<PathBuf>,
+}
+
+pub fn run(command: &IndexCommand) -> Result<()> {
+    match command {
+        IndexCommand::Diff(args) => run_diff(args),
+        IndexCommand::Apply(args) => run_apply(args),
+        IndexCommand::BuildKv(args) => run_build_kv(args),
+        IndexCommand::ConvertDb(args) => run_convert_db(args),
+    }
+}
+
+fn run_build_kv(args: &BuildKvArgs) -> Result<()> {
+    let tree = Tree::load_from_file(&args.database).with_context(|| {
+        format!("Failed to load binary index {}", args.database.display())
+    })?;
+    kv_index::build_to_file(&tree.k_mer_map, &args.output).with_context(|| {
+        format!("Failed to write KV sidecar index {}", args.output.display())
+    })?;
+    log::info!(
+        "Wrote KV sidecar index for {} references to {}",
+        tree.num_tips,
+        args.output.display()
+    );
+    Ok(())
+}
+
+fn run_convert_db(args: &ConvertDbArgs) -> Result<()> {
+    let from = db_backend::by_name(&args.from)?;
+    let to = db_backend::by_name(&args.to)?;
+    let tree = from.load(&args.input).with_context(|| {
+        format!(
+            "Failed to load {} as a '{}' database",
+            args.input.display(),
+            args.from
+        )
+    })?;
+    to.save(&tree, &args.output).with_context(|| {
+        format!(
+            "Failed to write {} as a '{}' database",
+            args.output.display(),
+            args.to
+        )
+    })?;
+    if let Some(checkpoint_path) = &args.checkpoint {
+        let ckp_file = std::fs::File::open(checkpoint_path)
+            .with_context(|| format!("Failed to open checkpoint {}", checkpoint_path.display()))?;
+        let mut checkpoint: Checkpoint = serde_json::from_reader(ckp_file)
+            .with_context(|| format!("{} is not a valid raxtax checkpoint", checkpoint_path.display()))?;
+        checkpoint.db_fingerprint = FileFingerprint::new(&args.output)?;
+        checkpoint.save()?;
+        log::info!(
+            "Updated checkpoint {} to point at {}",
+            checkpoint_path.display(),
+            args.output.display()
+        );
+    }
+    log::info!(
+        "Converted {} ({} references) from '{}' to '{}' format at {}",
+        args.input.display(),
+        tree.num_tips,
+        args.from,
+        args.to,
+        args.output.display()
+    );
+    Ok(())
+}
+
+fn reference_records_to_fasta(records: &[(String, Vec<u8>)]) -> String {
+    let mut fasta = String::new();
+    for (lineage, sequence) in records {
+        let sequence_str: String = sequence
+            .iter()
+            .map(|&code| parser::unmap_dna_char(code))
+            .collect();
+        fasta.push_str(&format!(">seq;tax={lineage};\n{sequence_str}\n"));
+    }
+    fasta
+}
+
+fn run_diff(args: &DiffArgs) -> Result<()> {
+    let base_bytes = std::fs::read(&args.base)
+        .with_context(|| format!("Failed to read base index {}", args.base.display()))?;
+    let base_tree: Tree = bincode::deserialize(&base_bytes)
+        .with_context(|| format!("{} is not a valid raxtax binary index", args.base.display()))?;
+    let base_fasta = reference_records_to_fasta(&base_tree.to_reference_records());
+    let mut add_fasta = String::new();
+    let _ = crate::utils::get_reader(&args.add)?.read_to_string(&mut add_fasta);
+    let merged_fasta = base_fasta + &add_fasta;
+    let updated_tree = parser::parse_reference_fasta_str(
+        &merged_fasta,
+        base_tree.k,
+        base_tree.scale,
+        base_tree.bloom_index.is_some(),
+        base_tree.hll_precision,
+        base_tree.max_ambiguity,
+    )?;
+    let updated_bytes = bincode::serialize(&updated_tree)?;
+    let patch = delta::encode_delta(&base_bytes, &updated_bytes);
+    std::fs::write(&args.delta, patch)
+        .with_context(|| format!("Failed to write delta patch {}", args.delta.display()))?;
+    log::info!(
+        "Wrote delta patch of {} bytes for an updated index of {} bytes ({} references)",
+        std::fs::metadata(&args.delta)?.len(),
+        updated_bytes.len(),
+        updated_tree.num_tips
+    );
+    Ok(())
+}
+
+fn run_apply(args: &ApplyArgs) -> Result<()> {
+    let base_bytes = std::fs::read(&args.base)
+        .with_context(|| format!("Failed to read base index {}", args.base.display()))?;
+    let patch = std::fs::read(&args.delta)
+        .with_context(|| format!("Failed to read delta patch {}", args.delta.display()))?;
+    let updated_bytes = delta::apply_delta(&base_bytes, &patch)
+        .context("Failed to apply delta patch")?;
+    // Round-trip through `Tree` once to fail loudly on a corrupt patch
+    // rather than silently writing out garbage bytes.
+    let _: Tree = bincode::deserialize(&updated_bytes)
+        .context("Delta patch did not reconstruct a valid raxtax binary index")?;
+    std::fs::write(&args.output, &updated_bytes)
+        .with_context(|| format!("Failed to write reconstructed index {}", args.output.display()))?;
+    Ok(())
+}
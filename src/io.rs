@@ -1,21 +1,31 @@
 use ahash::{HashSet, HashSetExt};
 use anyhow::{bail, Result};
+use clap::builder::TypedValueParser;
 use clap::Parser;
 use clap_verbosity_flag::{InfoLevel, Verbosity};
 use log::{info, Level};
 use serde::{Deserialize, Serialize};
 use std::{
     fs::{File, OpenOptions},
-    io::{BufWriter, Write},
+    io::{BufRead, BufReader, BufWriter, Write},
     path::{Path, PathBuf},
 };
 
-use crate::utils;
+use crate::{hll, utils};
 
 pub struct OutputWriters {
-    pub primary: BufWriter<File>,
+    /// `raxtax.out` by default, or stdout when `--query-file -` is used, so
+    /// raxtax can sit in a Unix pipe downstream of a demultiplexer or read
+    /// simulator.
+    pub primary: Box<dyn Write + Send>,
     pub tsv: Option<BufWriter<File>>,
     pub log: Box<dyn Write + Send>,
+    /// One query label per line, written right after that query's results
+    /// are written to `primary`/`tsv`. Each of these writers buffers on its
+    /// own and is flushed when the writer thread finishes. Read back on restart to seed
+    /// `Checkpoint::processed_queries`, so a resumed run skips queries a
+    /// prior, possibly interrupted, run already finished.
+    pub progress: BufWriter<File>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq)]
@@ -48,6 +58,14 @@ pub struct Checkpoint {
     raw_confidence: bool,
     skip_exact_matches: bool,
     tsv: bool,
+    kmer_size: usize,
+    scaled: u64,
+    bloom_prefilter: bool,
+    hll_precision: Option<usize>,
+    both_strands: bool,
+    detect_strand: bool,
+    max_ambiguity: usize,
+    kv_index: Option<PathBuf>,
     pub processed_queries: HashSet<String>,
 }
 
@@ -59,6 +77,14 @@ impl Checkpoint {
             raw_confidence: args.raw_confidence,
             skip_exact_matches: args.skip_exact_matches,
             tsv: args.tsv,
+            kmer_size: args.kmer_size,
+            scaled: args.scaled,
+            bloom_prefilter: args.bloom_prefilter,
+            hll_precision: args.hll.then_some(args.hll_precision),
+            both_strands: args.both_strands,
+            detect_strand: args.detect_strand,
+            max_ambiguity: args.max_ambiguity,
+            kv_index: args.kv_index.clone(),
             processed_queries: HashSet::new(),
         })
     }
@@ -70,6 +96,21 @@ impl Checkpoint {
         std::fs::rename(tmp_ckp_path, &self.checkpoint_file)?;
         Ok(())
     }
+
+    /// Removes this checkpoint's own bookkeeping files (the checkpoint JSON
+    /// and the query progress log) once a run has fully completed and
+    /// there's nothing left to resume. Leaves the actual result files
+    /// (`raxtax.out`, `raxtax.tsv`, `raxtax.log`) alone.
+    pub fn cleanup(&self) -> Result<()> {
+        if self.checkpoint_file.is_file() {
+            std::fs::remove_file(&self.checkpoint_file)?;
+        }
+        let progress_path = self.checkpoint_file.with_file_name("raxtax.progress");
+        if progress_path.is_file() {
+            std::fs::remove_file(progress_path)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Parser)]
@@ -78,8 +119,10 @@ pub struct Args {
     /// Path to the database fasta or bin file
     #[arg(short, long)]
     pub database_path: PathBuf,
-    /// Path to the query file
-    #[arg(short = 'i', long, required_unless_present = "only_db")]
+    /// Path to the query file, or "-" to read queries from stdin and stream
+    /// results to stdout as each batch finishes classifying, for use
+    /// downstream of a demultiplexer or read simulator in a Unix pipe
+    #[arg(short = 'i', long, required_unless_present = "only_db", verbatim_doc_comment)]
     pub query_file: Option<PathBuf>,
     /// If used for mislabling analysis, you want to skip exact sequence matches
     #[arg(long)]
@@ -96,10 +139,63 @@ pub struct Args {
     /// Don't adjust confidence values for 1 exact match
     #[arg(long)]
     pub raw_confidence: bool,
+    /// Length of the k-mers used for database and query matching
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u8).range(8..=16).map(usize::from))]
+    pub kmer_size: usize,
+    /// Also match k-mers from the reverse complement strand
+    /// This roughly doubles per-query work
+    #[arg(long, verbatim_doc_comment, conflicts_with = "detect_strand")]
+    pub both_strands: bool,
+    /// Classify both orientations separately and report whichever one fits
+    /// the model better, instead of merging their k-mers like --both-strands
+    /// does. Roughly doubles per-query work and adds a strand column to the
+    /// output.
+    #[arg(long, verbatim_doc_comment, conflicts_with = "both_strands")]
+    pub detect_strand: bool,
+    /// Skip k-mer windows overlapping an IUPAC ambiguity code if they would
+    /// expand into more than this many concrete k-mers. Applies to both the
+    /// reference database and queries.
+    #[arg(long, default_value_t = utils::DEFAULT_MAX_AMBIGUITY, verbatim_doc_comment)]
+    pub max_ambiguity: usize,
+    /// FracMinHash scaling factor: only keep k-mers whose hash falls below
+    /// 1/scale of the hash space. 1 disables sketching and keeps every k-mer
+    #[arg(long, default_value_t = utils::DEFAULT_SCALE, verbatim_doc_comment)]
+    pub scaled: u64,
+    /// Prune references with a Sequence-Bloom-Tree prefilter before exact
+    /// k-mer intersection counting
+    #[arg(long, verbatim_doc_comment)]
+    pub bloom_prefilter: bool,
+    /// Minimum fraction of query k-mers that must be present in a
+    /// Sequence-Bloom-Tree node's filter for its subtree to survive pruning
+    #[arg(long, default_value_t = utils::DEFAULT_BLOOM_THETA, verbatim_doc_comment)]
+    pub bloom_theta: f64,
+    /// Estimate per-reference k-mer intersections from HyperLogLog sketches
+    /// instead of exact counting, trading accuracy for a few KB of memory
+    /// per reference regardless of genome size
+    #[arg(long, verbatim_doc_comment)]
+    pub hll: bool,
+    /// HyperLogLog precision p (m = 2^p registers); relative error is
+    /// approximately 1.04 / sqrt(m), so small intersections are the least
+    /// reliable
+    #[arg(long, default_value_t = hll::DEFAULT_HLL_PRECISION, value_parser = clap::value_parser!(u8).range((hll::MIN_HLL_PRECISION as i64)..=(hll::MAX_HLL_PRECISION as i64)).map(usize::from), verbatim_doc_comment)]
+    pub hll_precision: usize,
+    /// Look up k-mer posting lists in a `raxtax index build-kv` sidecar
+    /// through a memory map instead of the in-memory k-mer map. The
+    /// reference database, including its own k-mer map, is still loaded
+    /// into RAM in full
+    #[arg(long, verbatim_doc_comment)]
+    pub kv_index: Option<PathBuf>,
     /// Number of threads
     /// If 0, uses all available threads
     #[arg(short, long, default_value_t = 0, verbatim_doc_comment)]
     pub threads: usize,
+    /// Target total residue count per query chunk handed to a rayon worker,
+    /// overriding the size automatically derived from thread count. Chunks
+    /// are still balanced by residues rather than query count, so a handful
+    /// of very long sequences don't starve other workers. Ignored with a
+    /// single thread or when all queries have the same length.
+    #[arg(long, verbatim_doc_comment)]
+    pub chunk_residues: Option<usize>,
     /// Output prefix
     #[arg(short = 'o', long, default_value = "raxtax")]
     pub prefix: PathBuf,
@@ -109,6 +205,10 @@ pub struct Args {
     /// Use thread pinning
     #[arg(long)]
     pub pin: bool,
+    /// Remove the checkpoint and progress files after a fully completed run,
+    /// instead of leaving them for a subsequent --redo-less rerun to reuse
+    #[arg(long)]
+    pub clean: bool,
     #[command(flatten)]
     pub verbosity: Verbosity<InfoLevel>,
 }
@@ -134,7 +234,7 @@ impl Args {
     pub fn get_output(&self) -> Result<(OutputWriters, Checkpoint)> {
         let prefix = self.get_prefix();
         let ckp_path = prefix.join("raxtax.ckp");
-        let checkpoint = if !self.redo && ckp_path.is_file() {
+        let mut checkpoint = if !self.redo && ckp_path.is_file() {
             let ckp_file = std::fs::File::open(&ckp_path)?;
             match serde_json::from_reader(ckp_file) {
                 Ok(ckp) => {
@@ -152,6 +252,17 @@ impl Args {
         } else {
             Checkpoint::new(&ckp_path, self)?
         };
+        let progress_path = prefix.join("raxtax.progress");
+        if !self.redo && progress_path.is_file() {
+            // The progress file gets one line per query, written right
+            // after that query's lines in `raxtax.out`/`raxtax.tsv`; trust it over
+            // whatever `processed_queries` the checkpoint JSON last saved,
+            // in case the process died between the two writes.
+            let progress_file = std::fs::File::open(&progress_path)?;
+            checkpoint.processed_queries = BufReader::new(progress_file)
+                .lines()
+                .collect::<std::io::Result<HashSet<String>>>()?;
+        }
         if prefix.is_dir() && !ckp_path.is_file() && !self.redo {
             bail!("Output folder {} already exists! Please specify another folder with -o <PATH> or run with --redo to force overriding existing files!", prefix.display());
         }
@@ -175,11 +286,21 @@ impl Args {
                 checkpoint.checkpoint_file.display()
             );
         }
+        let primary: Box<dyn Write + Send> = if self
+            .query_file
+            .as_deref()
+            .is_some_and(utils::is_stdin_path)
+        {
+            Box::new(std::io::stdout())
+        } else {
+            Box::new(create_file(prefix.join("raxtax.out"), !self.redo)?)
+        };
         Ok((
             OutputWriters {
-                primary: create_file(prefix.join("raxtax.out"), !self.redo)?,
+                primary,
                 tsv: tsv_output,
                 log: log_output,
+                progress: create_file(progress_path, !self.redo)?,
             },
             checkpoint,
         ))
@@ -215,6 +336,14 @@ impl Args {
                 self.tsv == checkpoint.tsv
                     && self.raw_confidence == checkpoint.raw_confidence
                     && self.skip_exact_matches == checkpoint.skip_exact_matches
+                    && self.kmer_size == checkpoint.kmer_size
+                    && self.scaled == checkpoint.scaled
+                    && self.bloom_prefilter == checkpoint.bloom_prefilter
+                    && self.hll.then_some(self.hll_precision) == checkpoint.hll_precision
+                    && self.both_strands == checkpoint.both_strands
+                    && self.detect_strand == checkpoint.detect_strand
+                    && self.max_ambiguity == checkpoint.max_ambiguity
+                    && self.kv_index == checkpoint.kv_index
                     && fp == checkpoint.db_fingerprint
             }
             Err(e) => {
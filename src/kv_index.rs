@@ -0,0 +1,154 @@
+//! Memory-mapped, lazily-paged [`KmerIndex`] backend.
+//!
+//! `Tree::load_from_file` deserializes the entire in-memory [`KmerMap`] up
+//! front, which is prohibitive for the largest BOLD/UNITE-scale databases
+//! under `--features huge_db`. [`KvKmerIndex`] instead stores each
+//! non-empty k-mer's posting list as a `(k_mer, offset, len)` entry in a
+//! small sorted header, followed by the posting lists themselves packed
+//! back-to-back; only the header is read eagerly. The whole file is
+//! memory-mapped once on open, and a lookup only touches the slice of the
+//! mapping that a query's k-mer actually needs.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem::size_of;
+use std::path::Path;
+
+use anyhow::{ensure, Context, Result};
+use memmap2::Mmap;
+
+use crate::tree::{IndexType, KmerIndex, KmerMap};
+
+/// Builds a `KvKmerIndex` file from an in-memory [`KmerMap`]'s postings.
+pub fn build_to_file(map: &KmerMap, path: &Path) -> Result<()> {
+    let mut entries: Vec<(u32, &[IndexType])> = map.iter().collect();
+    entries.sort_unstable_by_key(|&(k_mer, _)| k_mer);
+
+    let mut writer = BufWriter::new(
+        File::create(path)
+            .with_context(|| format!("Failed to create KV index file {}", path.display()))?,
+    );
+    writer.write_all(&(entries.len() as u64).to_ne_bytes())?;
+    let mut elem_offset: u64 = 0;
+    for &(k_mer, postings) in &entries {
+        writer.write_all(&k_mer.to_ne_bytes())?;
+        writer.write_all(&elem_offset.to_ne_bytes())?;
+        writer.write_all(&(postings.len() as u32).to_ne_bytes())?;
+        elem_offset += postings.len() as u64;
+    }
+    for &(_, postings) in &entries {
+        // Safety: `IndexType` (`u32`/`usize`) has no padding or invalid bit
+        // patterns, so reinterpreting it as raw bytes for storage is sound.
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                postings.as_ptr().cast::<u8>(),
+                std::mem::size_of_val(postings),
+            )
+        };
+        writer.write_all(bytes)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub struct KvKmerIndex {
+    mmap: Mmap,
+    /// `(k_mer, element offset into the data section, element count)`,
+    /// sorted by `k_mer` for binary search.
+    header: Vec<(u32, u64, u32)>,
+    data_offset: usize,
+}
+
+impl KvKmerIndex {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("Failed to open KV index {}", path.display()))?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        ensure!(mmap.len() >= 8, "KV index file is too short to contain a header");
+        let count = u64::from_ne_bytes(mmap[0..8].try_into().unwrap()) as usize;
+        const ENTRY_SIZE: usize = size_of::<u32>() + size_of::<u64>() + size_of::<u32>();
+        let header_bytes = count * ENTRY_SIZE;
+        ensure!(
+            mmap.len() >= 8 + header_bytes,
+            "KV index file is truncated: header claims {count} entries"
+        );
+        let mut header = Vec::with_capacity(count);
+        let mut pos = 8;
+        for _ in 0..count {
+            let k_mer = u32::from_ne_bytes(mmap[pos..pos + 4].try_into().unwrap());
+            let offset = u64::from_ne_bytes(mmap[pos + 4..pos + 12].try_into().unwrap());
+            let len = u32::from_ne_bytes(mmap[pos + 12..pos + 16].try_into().unwrap());
+            header.push((k_mer, offset, len));
+            pos += ENTRY_SIZE;
+        }
+        let data_offset = pos;
+        for &(k_mer, offset, len) in &header {
+            let byte_end = data_offset + (offset + u64::from(len)) as usize * size_of::<IndexType>();
+            ensure!(
+                byte_end <= mmap.len(),
+                "KV index file is truncated: postings for k-mer {k_mer} extend past EOF"
+            );
+        }
+        Ok(Self {
+            mmap,
+            header,
+            data_offset,
+        })
+    }
+}
+
+impl KmerIndex for KvKmerIndex {
+    fn postings(&self, k_mer: u32) -> &[IndexType] {
+        let Ok(idx) = self.header.binary_search_by_key(&k_mer, |&(k, _, _)| k) else {
+            return &[];
+        };
+        let (_, elem_offset, elem_len) = self.header[idx];
+        let byte_start = self.data_offset + elem_offset as usize * size_of::<IndexType>();
+        let byte_len = elem_len as usize * size_of::<IndexType>();
+        // Safety: `build_to_file` wrote exactly `elem_len` contiguous
+        // `IndexType` values at this byte offset, and the mmap keeps the
+        // backing file alive for `self`'s lifetime.
+        unsafe {
+            std::slice::from_raw_parts(
+                self.mmap[byte_start..byte_start + byte_len]
+                    .as_ptr()
+                    .cast::<IndexType>(),
+                elem_len as usize,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_to_file, KvKmerIndex};
+    use crate::tree::KmerIndex;
+
+    #[test]
+    fn test_round_trip_via_dense_kmer_map() {
+        let fasta_str = r">a;tax=p:P1,c:C1,o:O1,f:F1,g:G1,s:S1;
+AAACCCTTTGGGA
+>b;tax=p:P1,c:C1,o:O1,f:F1,g:G1,s:S2;
+ATACGCTTTGGGA";
+        let tree = crate::parser::parse_reference_fasta_str(
+            fasta_str,
+            8,
+            1,
+            false,
+            None,
+            crate::utils::DEFAULT_MAX_AMBIGUITY,
+        )
+        .unwrap();
+        let tmp = std::env::temp_dir().join(format!(
+            "raxtax_kv_index_test_{}.kv",
+            std::process::id()
+        ));
+        build_to_file(&tree.k_mer_map, &tmp).unwrap();
+        let kv_index = KvKmerIndex::open(&tmp).unwrap();
+        for (k_mer, expected) in tree.k_mer_map.iter() {
+            assert_eq!(kv_index.postings(k_mer), expected);
+        }
+        assert_eq!(kv_index.postings(u32::MAX), &[] as &[u32]);
+        std::fs::remove_file(&tmp).unwrap();
+    }
+}
@@ -0,0 +1,14 @@
+pub mod db_backend;
+pub mod delta;
+pub mod hll;
+pub mod index;
+pub mod io;
+pub mod kv_index;
+pub mod lineage;
+pub mod parser;
+pub mod prob;
+pub mod raxtax;
+pub mod sbt;
+pub mod serve;
+pub mod tree;
+pub mod utils;
@@ -2,7 +2,7 @@ use crate::tree::{Node, Tree};
 use itertools::Itertools;
 use logging_timer::time;
 
-use crate::utils;
+use crate::utils::{self, Strand};
 
 #[derive(Debug, Clone)]
 pub struct EvaluationResult<'a, 'b> {
@@ -11,12 +11,19 @@ pub struct EvaluationResult<'a, 'b> {
     pub confidence_values: Vec<f64>,
     pub local_signal: f64,
     pub global_signal: f64,
+    /// Which orientation of the query this result was classified from; see
+    /// [`Strand`].
+    pub strand: Strand,
 }
 
 impl EvaluationResult<'_, '_> {
-    pub fn get_output_string(&self) -> String {
+    /// `show_strand` appends the resolved orientation as a trailing column;
+    /// only set it when `--both-strands`/`--detect-strand` was requested, so
+    /// the default forward-only output keeps its existing schema for
+    /// downstream parsers.
+    pub fn get_output_string(&self, show_strand: bool) -> String {
         format!(
-            "{}\t{}\t{}\t{:.5}\t{:.5}",
+            "{}\t{}\t{}\t{:.5}\t{:.5}{}",
             self.query_label,
             self.lineage,
             self.confidence_values
@@ -24,13 +31,16 @@ impl EvaluationResult<'_, '_> {
                 .map(|v| format!("{1:.0$}", utils::F64_OUTPUT_ACCURACY as usize, v))
                 .join(","),
             self.local_signal,
-            self.global_signal
+            self.global_signal,
+            show_strand
+                .then(|| format!("\t{}", self.strand.as_str()))
+                .unwrap_or_default()
         )
     }
 
-    pub fn get_tsv_string(&self, sequence: &String) -> String {
+    pub fn get_tsv_string(&self, sequence: &String, show_strand: bool) -> String {
         format!(
-            "{}\t{}\t{:.5}\t{:.5}\t{}",
+            "{}\t{}\t{:.5}\t{:.5}{}\t{}",
             self.query_label,
             self.lineage
                 .split(',')
@@ -43,6 +53,9 @@ impl EvaluationResult<'_, '_> {
                 .join("\t"),
             self.local_signal,
             self.global_signal,
+            show_strand
+                .then(|| format!("\t{}", self.strand.as_str()))
+                .unwrap_or_default(),
             sequence
         )
     }
@@ -55,10 +68,16 @@ pub struct Lineage<'a, 'b> {
     confidence_prefix_sum: Vec<f64>,
     confidence_vectors: Vec<(usize, Vec<f64>, Vec<f64>)>,
     rounding_factor: f64,
+    strand: Strand,
 }
 
 impl<'a, 'b> Lineage<'a, 'b> {
-    pub fn new(query_label: &'b String, tree: &'a Tree, confidence_values: Vec<f64>) -> Self {
+    pub fn new(
+        query_label: &'b String,
+        tree: &'a Tree,
+        confidence_values: Vec<f64>,
+        strand: Strand,
+    ) -> Self {
         let mut confidence_prefix_sum = vec![0.0];
         confidence_prefix_sum.extend(confidence_values.iter().scan(0.0, |sum, i| {
             *sum += i;
@@ -73,6 +92,7 @@ impl<'a, 'b> Lineage<'a, 'b> {
             confidence_prefix_sum,
             confidence_vectors: Vec::with_capacity(expected_num_results),
             rounding_factor,
+            strand,
         }
     }
 
@@ -106,6 +126,7 @@ impl<'a, 'b> Lineage<'a, 'b> {
                     confidence_values: conf_values,
                     local_signal: lineage_confidence,
                     global_signal: leaf_confidence,
+                    strand: self.strand,
                 }
             })
             .collect_vec()
@@ -186,6 +207,7 @@ mod tests {
     use crate::{
         lineage::{EvaluationResult, Lineage},
         tree::Tree,
+        utils::Strand,
     };
 
     #[test]
@@ -204,11 +226,20 @@ mod tests {
             [0b00].repeat(9),
             [0b00].repeat(9),
         ];
-        let tree = Tree::new(lineages, sequences).unwrap();
+        let tree = Tree::new(
+            lineages,
+            sequences,
+            8,
+            1,
+            false,
+            None,
+            crate::utils::DEFAULT_MAX_AMBIGUITY,
+        )
+        .unwrap();
         let confidence_values = vec![0.1, 0.3, 0.4, 0.004, 0.004];
         tree.print();
         let query_label = String::from("q");
-        let lineage = Lineage::new(&query_label, &tree, confidence_values);
+        let lineage = Lineage::new(&query_label, &tree, confidence_values, Strand::Forward);
         let result = lineage.evaluate();
         assert_eq!(
             result
@@ -258,11 +289,20 @@ mod tests {
             [0b00].repeat(9),
             [0b00].repeat(9),
         ];
-        let tree = Tree::new(lineages, sequences).unwrap();
+        let tree = Tree::new(
+            lineages,
+            sequences,
+            8,
+            1,
+            false,
+            None,
+            crate::utils::DEFAULT_MAX_AMBIGUITY,
+        )
+        .unwrap();
         let confidence_values = vec![0.05, 0.1, 0.3, 0.4, 0.1, 0.004, 0.004];
         tree.print();
         let query_label = String::from("q");
-        let lineage = Lineage::new(&query_label, &tree, confidence_values);
+        let lineage = Lineage::new(&query_label, &tree, confidence_values, Strand::Forward);
         let result = lineage.evaluate();
         dbg!(&result);
         assert_eq!(
@@ -309,11 +349,20 @@ mod tests {
             "Animalia,Chordata,Mammalia,Carnivora,Canidae,Canis".into(),
         ];
         let sequences = vec![[0b00].repeat(9), [0b00].repeat(9), [0b00].repeat(9)];
-        let tree = Tree::new(lineages, sequences).unwrap();
+        let tree = Tree::new(
+            lineages,
+            sequences,
+            8,
+            1,
+            false,
+            None,
+            crate::utils::DEFAULT_MAX_AMBIGUITY,
+        )
+        .unwrap();
         let confidence_values = vec![0.004, 0.004, 0.004];
         tree.print();
         let query_label = String::from("q");
-        let lineage = Lineage::new(&query_label, &tree, confidence_values);
+        let lineage = Lineage::new(&query_label, &tree, confidence_values, Strand::Forward);
         let result = lineage.evaluate();
         assert_eq!(
             result
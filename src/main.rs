@@ -1,17 +1,62 @@
 use std::process::exit;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use clap::Parser;
 use log::Level;
 use logging_timer::timer;
+use raxtax::index::{self, IndexCommand};
 use raxtax::io;
 use raxtax::io::FileFingerprint;
+use raxtax::kv_index::KvKmerIndex;
 use raxtax::parser;
 use raxtax::raxtax::raxtax;
+use raxtax::serve::{self, ServeArgs};
+use raxtax::tree::KmerIndex;
 use raxtax::utils;
 use std::io::Write;
 
+/// Conventional Unix "killed by SIGINT" status (128 + signal 2); also used
+/// for a SIGTERM-triggered shutdown, since both land in the same
+/// checkpoint-flushing exit path below.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Thin wrapper so `raxtax index diff|apply ...` can be parsed on its own,
+/// ahead of the classify-mode `io::Args`, which has no subcommands of its
+/// own and must stay parsable the same way it always has been.
+#[derive(Parser)]
+struct IndexCli {
+    #[command(subcommand)]
+    command: IndexCommand,
+}
+
+/// Same rationale as [`IndexCli`], for `raxtax serve --database ... --host ...`.
+#[derive(Parser)]
+struct ServeCli {
+    #[command(flatten)]
+    args: ServeArgs,
+}
+
 fn main() {
+    // `index` and `serve` are the subcommands raxtax has; every other
+    // invocation (including all of classify mode's flags) goes through
+    // `io::Args` unchanged, so we only special-case these leading tokens.
+    if std::env::args().nth(1).as_deref() == Some("index") {
+        let cli = IndexCli::parse_from(std::env::args().skip(1));
+        if let Err(e) = index::run(&cli.command) {
+            eprintln!("\x1b[31m[ERROR]\x1b[0m {e:#}");
+            exit(exitcode::SOFTWARE);
+        }
+        exit(exitcode::OK);
+    }
+    if std::env::args().nth(1).as_deref() == Some("serve") {
+        let cli = ServeCli::parse_from(std::env::args().skip(1));
+        if let Err(e) = serve::run(&cli.args) {
+            eprintln!("\x1b[31m[ERROR]\x1b[0m {e:#}");
+            exit(exitcode::SOFTWARE);
+        }
+        exit(exitcode::OK);
+    }
+
     // Parse args, set up files and other context
     let args = io::Args::parse();
     let (
@@ -58,8 +103,15 @@ fn main() {
     let _total_tmr = timer!(Level::Info; "Total Runtime");
 
     // Parse reference databse
-    let (store_db, tree) = parser::parse_reference_fasta_file(&checkpoint.db_fingerprint.path)
-        .unwrap_or_else(|e| {
+    let (store_db, tree) = parser::parse_reference_fasta_file(
+        &checkpoint.db_fingerprint.path,
+        args.kmer_size,
+        args.scaled,
+        args.bloom_prefilter,
+        args.hll.then_some(args.hll_precision),
+        args.max_ambiguity,
+    )
+    .unwrap_or_else(|e| {
             utils::report_error(
                 e,
                 format!(
@@ -102,27 +154,41 @@ fn main() {
         exit(exitcode::OK);
     }
 
-    // Parse queries
-    let queries = parser::parse_query_fasta_file(
-        args.query_file.as_ref().unwrap(),
-        &checkpoint.processed_queries,
-    )
-    .unwrap_or_else(|e| {
+    let query_file = args.query_file.clone().unwrap();
+    let streaming = utils::is_stdin_path(&query_file);
+    let n_threads = rayon::current_num_threads();
+
+    // When `--kv-index` points at a `raxtax index build-kv` sidecar, look up
+    // posting lists in its memory map instead of `tree.k_mer_map`; `tree`,
+    // including its k-mer map, is loaded in full either way.
+    let kv_index = args
+        .kv_index
+        .as_ref()
+        .map(|path| {
+            KvKmerIndex::open(path)
+                .with_context(|| format!("Failed to open KV index {}", path.display()))
+        })
+        .transpose()
+        .unwrap_or_else(|e| {
+            utils::report_error(e, "Failed to open --kv-index");
+            exit(exitcode::NOINPUT);
+        });
+    let kmer_index: &dyn KmerIndex = kv_index
+        .as_ref()
+        .map_or(&tree.k_mer_map as &dyn KmerIndex, |kv| kv as &dyn KmerIndex);
+
+    // A SIGINT/SIGTERM between chunks should stop dispatching new work and
+    // exit cleanly with whatever has already been flushed, instead of
+    // killing the process mid-write and leaving `raxtax.out`/`raxtax.ckp`
+    // inconsistent.
+    let cancelled = utils::install_cancellation_handler().unwrap_or_else(|e| {
         utils::report_error(
             e,
-            format!("Failed to parse {}", args.query_file.unwrap().display()),
+            "Failed to install SIGINT/SIGTERM handler; Ctrl-C will terminate immediately instead of flushing a checkpoint",
         );
-        exit(exitcode::NOINPUT);
+        std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false))
     });
 
-    // Compute query results and output to files
-    let n_threads = rayon::current_num_threads();
-    let chunk_size = if n_threads == 1 {
-        queries.len()
-    } else {
-        ((queries.len() / (n_threads * 10)) + 1).max(100)
-    };
-
     let (sender, receiver) = crossbeam::channel::unbounded::<(String, String, Option<String>)>();
     let writer_handle = std::thread::spawn(move || -> Result<()> {
         for (query, results, tsv_results) in receiver {
@@ -132,32 +198,106 @@ fn main() {
             writeln!(output, "{}", results)?;
             writeln!(progress_output, "{}", query)?;
         }
+        output.flush()?;
+        progress_output.flush()?;
+        if let Some(ref mut tsv_output) = tsv_output {
+            tsv_output.flush()?;
+        }
         Ok(())
     });
-    let ok = raxtax(
-        &queries,
-        &tree,
-        args.skip_exact_matches,
-        args.raw_confidence,
-        chunk_size,
-        &sender,
-        args.tsv,
-    );
-    drop(sender);
-    if writer_handle.join().is_err() {
-        utils::report_error(
-            anyhow!("IO-thread could not be joined. Check if results are complete!"),
-            "",
+
+    // Total queries sent to `raxtax()` across however many batches were read;
+    // in streaming mode this isn't known up front, unlike the buffered path.
+    let mut total_queries = 0_usize;
+    // Queries finished by earlier runs, so the interrupt message below can
+    // report only this run's share.
+    let previously_processed = checkpoint.processed_queries.len();
+    if streaming {
+        // True streaming: read and classify stdin one bounded batch at a
+        // time instead of buffering the whole input, so `raxtax --query-file
+        // -` can sit at the end of a long-running upstream process and start
+        // emitting results long before that process's output ends.
+        let mut record_reader =
+            parser::FastaRecordReader::new(std::io::BufReader::new(std::io::stdin()));
+        while !cancelled.load(std::sync::atomic::Ordering::Relaxed) {
+            let mut batch = record_reader
+                .next_batch(utils::STREAM_BATCH_QUERIES)
+                .unwrap_or_else(|e| {
+                    utils::report_error(e, "Failed to parse queries from stdin");
+                    exit(exitcode::NOINPUT);
+                });
+            if batch.is_empty() {
+                break;
+            }
+            batch.retain(|(label, _)| !checkpoint.processed_queries.contains(label));
+            total_queries += batch.len();
+            let chunk_sizes = utils::chunk_queries_by_residues(&batch, n_threads, args.chunk_residues);
+            let sent_queries = raxtax(
+                &batch,
+                &tree,
+                kmer_index,
+                args.skip_exact_matches,
+                args.raw_confidence,
+                args.both_strands,
+                args.detect_strand,
+                args.max_ambiguity,
+                args.bloom_theta,
+                &chunk_sizes,
+                &sender,
+                args.tsv,
+                &cancelled,
+            );
+            checkpoint.processed_queries.extend(sent_queries);
+        }
+    } else {
+        let queries = parser::parse_query_fasta_file(&query_file, &checkpoint.processed_queries)
+            .unwrap_or_else(|e| {
+                utils::report_error(e, format!("Failed to parse {}", query_file.display()));
+                exit(exitcode::NOINPUT);
+            });
+        total_queries = queries.len();
+        let chunk_sizes =
+            utils::chunk_queries_by_residues(&queries, n_threads, args.chunk_residues);
+        let sent_queries = raxtax(
+            &queries,
+            &tree,
+            kmer_index,
+            args.skip_exact_matches,
+            args.raw_confidence,
+            args.both_strands,
+            args.detect_strand,
+            args.max_ambiguity,
+            args.bloom_theta,
+            &chunk_sizes,
+            &sender,
+            args.tsv,
+            &cancelled,
         );
-    };
-    if let Err(e) = ok {
-        utils::report_error(
+        checkpoint.processed_queries.extend(sent_queries);
+    }
+    drop(sender);
+    match writer_handle.join() {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => utils::report_error(
             e,
-            "Error while sending results to IO-thread!\n
-            Rerun raxtax to continue from the last checkpoint.\n
-            If the problem persists, please report this issue at: https://github.com/noahares/raxtax/issues",
+            "IO-thread failed while writing results! Results past that point are incomplete",
+        ),
+        Err(_) => utils::report_error(
+            anyhow!("IO-thread panicked while writing results! Results past that point are incomplete"),
+            "",
+        ),
+    }
+    checkpoint.save().unwrap_or_else(|e| {
+        utils::report_error(e, "Failed to write checkpoint! Continuing without...")
+    });
+
+    if cancelled.load(std::sync::atomic::Ordering::Relaxed) {
+        log::info!(
+            "Interrupted: flushed {} of {} queries this run; rerun the same command to resume",
+            checkpoint.processed_queries.len() - previously_processed,
+            total_queries
         );
-        exit(exitcode::TEMPFAIL);
+        exit(INTERRUPTED_EXIT_CODE);
     }
 
     if args.clean {
@@ -1,3 +1,4 @@
+use ahash::HashSet;
 use anyhow::{bail, Context, Result};
 use indicatif::{ProgressIterator, ProgressStyle};
 use log::Level;
@@ -32,17 +33,74 @@ fn map_dna_char(ch: char) -> u8 {
     }
 }
 
+/// Inverse of [`map_dna_char`]: recovers the IUPAC character for one of the
+/// 15 non-zero nibble codes a reference/query sequence byte can hold.
+pub(crate) fn unmap_dna_char(code: u8) -> char {
+    match code {
+        0b0001 => 'A',
+        0b0010 => 'C',
+        0b0100 => 'G',
+        0b1000 => 'T',
+        0b1001 => 'W',
+        0b0110 => 'S',
+        0b0011 => 'M',
+        0b1100 => 'K',
+        0b0101 => 'R',
+        0b1010 => 'Y',
+        0b1110 => 'B',
+        0b1101 => 'D',
+        0b1011 => 'H',
+        0b0111 => 'V',
+        0b1111 => 'N',
+        _ => panic!("Unexpected nibble code: {code}"),
+    }
+}
+
 #[time("info")]
-pub fn parse_reference_fasta_file(sequence_path: &PathBuf) -> Result<(bool, Tree)> {
+pub fn parse_reference_fasta_file(
+    sequence_path: &PathBuf,
+    k: usize,
+    scale: u64,
+    bloom_prefilter: bool,
+    hll_precision: Option<usize>,
+    max_ambiguity: usize,
+) -> Result<(bool, Tree)> {
     if let Ok(tree) = Tree::load_from_file(sequence_path) {
-        return Ok((false, tree));
+        if tree.k == k
+            && tree.scale == scale
+            && tree.bloom_index.is_some() == bloom_prefilter
+            && tree.hll_precision == hll_precision
+        {
+            return Ok((false, tree));
+        }
+        log::info!(
+            "Cached database was built with k={}, scale={}, bloom_prefilter={}, hll_precision={:?}, but k={}, scale={}, bloom_prefilter={}, hll_precision={:?} was requested. Rebuilding...",
+            tree.k,
+            tree.scale,
+            tree.bloom_index.is_some(),
+            tree.hll_precision,
+            k,
+            scale,
+            bloom_prefilter,
+            hll_precision
+        );
     }
     let mut fasta_str = String::new();
     let _ = utils::get_reader(sequence_path)?.read_to_string(&mut fasta_str);
-    Ok((true, parse_reference_fasta_str(&fasta_str)?))
+    Ok((
+        true,
+        parse_reference_fasta_str(&fasta_str, k, scale, bloom_prefilter, hll_precision, max_ambiguity)?,
+    ))
 }
 
-fn parse_reference_fasta_str(fasta_str: &str) -> Result<Tree> {
+pub(crate) fn parse_reference_fasta_str(
+    fasta_str: &str,
+    k: usize,
+    scale: u64,
+    bloom_prefilter: bool,
+    hll_precision: Option<usize>,
+    max_ambiguity: usize,
+) -> Result<Tree> {
     if fasta_str.is_empty() {
         bail!("File is empty")
     }
@@ -100,17 +158,114 @@ fn parse_reference_fasta_str(fasta_str: &str) -> Result<Tree> {
         }
         (labels, sequences)
     };
-    Tree::new(labels, sequences)
+    Tree::new(
+        labels,
+        sequences,
+        k,
+        scale,
+        bloom_prefilter,
+        hll_precision,
+        max_ambiguity,
+    )
 }
 
 #[time("info")]
-pub fn parse_query_fasta_file(sequence_path: &PathBuf) -> Result<Vec<(String, Vec<u8>)>> {
+pub fn parse_query_fasta_file(
+    sequence_path: &PathBuf,
+    processed_queries: &HashSet<String>,
+) -> Result<Vec<(String, Vec<u8>)>> {
     let mut fasta_str = String::new();
     let _ = utils::get_reader(sequence_path)?.read_to_string(&mut fasta_str);
-    parse_query_fasta_str(&fasta_str)
+    let queries = parse_query_fasta_str(&fasta_str)?;
+    if processed_queries.is_empty() {
+        return Ok(queries);
+    }
+    // Resuming from a checkpoint: skip queries a prior run already flushed
+    // to the output files instead of reclassifying and re-appending them.
+    Ok(queries
+        .into_iter()
+        .filter(|(label, _)| !processed_queries.contains(label))
+        .collect())
 }
 
-fn parse_query_fasta_str(fasta_str: &str) -> Result<Vec<(String, Vec<u8>)>> {
+/// Reads one FASTA record at a time off a buffered stream instead of
+/// requiring the whole input up front, so `--query-file -` can classify
+/// queries as they arrive from a long-running upstream process rather than
+/// waiting for stdin to close.
+pub struct FastaRecordReader<R> {
+    reader: R,
+    pending_label: Option<String>,
+}
+
+impl<R: std::io::BufRead> FastaRecordReader<R> {
+    pub fn new(reader: R) -> Self {
+        FastaRecordReader {
+            reader,
+            pending_label: None,
+        }
+    }
+
+    /// Returns the next record, or `None` once the stream is exhausted.
+    fn next_record(&mut self) -> Result<Option<(String, Vec<u8>)>> {
+        let label = match self.pending_label.take() {
+            Some(label) => label,
+            None => match self.next_label()? {
+                Some(label) => label,
+                None => return Ok(None),
+            },
+        };
+        let mut sequence = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            if let Some(next_label) = line.strip_prefix('>') {
+                self.pending_label = Some(next_label.to_string());
+                break;
+            }
+            sequence.extend(line.chars().map(map_dna_char));
+        }
+        Ok(Some((label, sequence)))
+    }
+
+    fn next_label(&mut self) -> Result<Option<String>> {
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            return match line.strip_prefix('>') {
+                Some(label) => Ok(Some(label.to_string())),
+                None => bail!("Not a valid FASTA file"),
+            };
+        }
+    }
+
+    /// Reads up to `batch_size` records, stopping early at the stream's end.
+    /// An empty, non-full batch signals the caller that the stream is
+    /// exhausted.
+    pub fn next_batch(&mut self, batch_size: usize) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut batch = Vec::new();
+        while batch.len() < batch_size {
+            match self.next_record()? {
+                Some(record) => batch.push(record),
+                None => break,
+            }
+        }
+        Ok(batch)
+    }
+}
+
+pub(crate) fn parse_query_fasta_str(fasta_str: &str) -> Result<Vec<(String, Vec<u8>)>> {
     if fasta_str.is_empty() {
         bail!("File is empty")
     }
@@ -148,6 +303,7 @@ mod tests {
     use itertools::Itertools;
 
     use crate::tree::Tree;
+    use crate::utils;
 
     use super::{parse_query_fasta_str, parse_reference_fasta_str};
 
@@ -165,30 +321,22 @@ ATACGCTTTGCGT
 GTGCGCTATGCGA
 >Badabing|Badabum;tax=p:Phylum2,c:Class3,o:Order3,f:Family4,g:Genus4,s:Species5;
 ATACGCTTTGCGT";
-        let tree = parse_reference_fasta_str(fasta_str).unwrap();
-        for (k, v) in tree.k_mer_map.iter().enumerate() {
-            if !v.is_empty() {
-                println!("{k:b}:\n {v:?}");
-            }
-        }
+        let tree = parse_reference_fasta_str(fasta_str, 8, 1, false, None, utils::DEFAULT_MAX_AMBIGUITY).unwrap();
         assert_eq!(
-            tree.k_mer_map[0b1_0101_1111_1110_usize]
-                .iter()
-                .collect_vec(),
-            &[&0]
+            tree.k_mer_map.get(0b1_0101_1111_1110).to_vec(),
+            vec![0]
         );
         assert_eq!(
-            tree.k_mer_map[0b11_0001_1001_1111_usize]
+            tree.k_mer_map
+                .get(0b11_0001_1001_1111)
                 .iter()
                 .sorted()
                 .collect_vec(),
-            &[&1, &4, &5]
+            vec![&1, &4, &5]
         );
         assert_eq!(
-            tree.k_mer_map[0b110_0111_0011_1010_usize]
-                .iter()
-                .collect_vec(),
-            &[&3]
+            tree.k_mer_map.get(0b110_0111_0011_1010).to_vec(),
+            vec![3]
         );
         assert_eq!(tree.num_tips, 6);
         assert_eq!(
@@ -232,57 +380,38 @@ TTTAAAACC
 TTTAAAACA
 >Badabing|Badabum;tax=p:Phylum1,c:Class2,o:Order2,f:Family2,g:Genus3,s:Species4;
 AAACCCCGG";
-        let Tree { k_mer_map, .. } = parse_reference_fasta_str(fasta_str).unwrap();
-        for (k, v) in k_mer_map.iter().enumerate() {
-            if !v.is_empty() {
-                println!("{k:b}:\n {v:?}");
-            }
-        }
-        assert_eq!(
-            k_mer_map[0b1_0101_0110_usize].iter().sorted().collect_vec(),
-            &[&0, &4]
-        );
+        let Tree { k_mer_map, .. } = parse_reference_fasta_str(fasta_str, 8, 1, false, None, utils::DEFAULT_MAX_AMBIGUITY).unwrap();
         assert_eq!(
-            k_mer_map[0b101_0101_1010_usize]
-                .iter()
-                .sorted()
-                .collect_vec(),
-            &[&1, &4]
+            k_mer_map.get(0b1_0101_0110).iter().sorted().collect_vec(),
+            vec![&0, &4]
         );
         assert_eq!(
-            k_mer_map[0b101_0101_1011_usize]
+            k_mer_map
+                .get(0b101_0101_1010)
                 .iter()
                 .sorted()
                 .collect_vec(),
-            &[&0]
+            vec![&1, &4]
         );
         assert_eq!(
-            k_mer_map[0b1100_0001_0101_0110_usize]
-                .iter()
-                .sorted()
-                .collect_vec(),
-            &[&1]
+            k_mer_map.get(0b101_0101_1011).to_vec(),
+            vec![0]
         );
         assert_eq!(
-            k_mer_map[0b1111_0000_0000_0101_usize]
-                .iter()
-                .sorted()
-                .collect_vec(),
-            &[&2]
+            k_mer_map.get(0b1100_0001_0101_0110).to_vec(),
+            vec![1]
         );
         assert_eq!(
-            k_mer_map[0b1111_0000_0000_0101_usize]
-                .iter()
-                .sorted()
-                .collect_vec(),
-            &[&2]
+            k_mer_map.get(0b1111_0000_0000_0101).to_vec(),
+            vec![2]
         );
         assert_eq!(
-            k_mer_map[0b1111_1100_0000_0001_usize]
+            k_mer_map
+                .get(0b1111_1100_0000_0001)
                 .iter()
                 .sorted()
                 .collect_vec(),
-            &[&2, &3]
+            vec![&2, &3]
         );
     }
 }
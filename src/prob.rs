@@ -4,12 +4,19 @@ use itertools::Itertools;
 use logging_timer::time;
 use statrs::function::factorial::ln_binomial;
 
+/// Returns the per-reference highest-hit probabilities (normalized to sum
+/// to 1, in `intersection_sizes`' order) alongside the raw, pre-normalization
+/// probability mass they were scaled from. The raw sum is a cheap proxy for
+/// how well this set of intersection sizes fits the model at all: an
+/// antisense read aligned in the wrong orientation produces a much smaller
+/// raw sum than the same read's true orientation, which `raxtax::raxtax`
+/// uses to pick between strands (see `--detect-strand`).
 #[time("debug")]
 pub fn highest_hit_prob_per_reference(
     total_num_k_mers: u16,
     num_trials: usize,
     intersection_sizes: &[u16],
-) -> Vec<f64> {
+) -> (Vec<f64>, f64) {
     let intersection_size_counts = {
         let mut counts: HashMap<u16, usize> = HashMap::new();
         intersection_sizes
@@ -96,10 +103,13 @@ pub fn highest_hit_prob_per_reference(
 
     let probs_sum: f64 = highest_hit_probs.iter().sum();
     assert!(probs_sum > 0.0);
-    highest_hit_probs
-        .into_iter()
-        .map(|v| v / probs_sum)
-        .collect_vec()
+    (
+        highest_hit_probs
+            .into_iter()
+            .map(|v| v / probs_sum)
+            .collect_vec(),
+        probs_sum,
+    )
 }
 
 fn only_last_pmf(
@@ -118,57 +128,200 @@ fn only_last_pmf(
     (num_possible_matches - num_possible_kmer_sets).exp()
 }
 
-fn iterative_pmfs_ln(
+/// Below this many distinct intersection sizes, the exact per-size PMF is
+/// cheap enough that interpolating it isn't worth the setup cost.
+const LAGRANGE_FAST_PATH_MIN_DISTINCT_SIZES: usize = 32;
+/// Number of grid points `d` the fast path samples exactly before
+/// interpolating the rest; i.e. the degree of the fitted polynomial is
+/// `d - 1`.
+const LAGRANGE_GRID_POINTS: usize = 12;
+
+fn exact_pmf_ln(
     total_num_k_mers: u64,
     num_trials: u64,
-    intersection_sizes: &HashMap<u16, usize>,
+    num_intersections: u16,
+    num_possible_kmer_sets: f64,
+) -> Vec<f64> {
+    if num_intersections as u64 == total_num_k_mers {
+        let mut res = vec![f64::NEG_INFINITY; num_trials as usize + 1];
+        res[num_trials as usize] = 0.0;
+        res
+    } else if num_intersections == 0 {
+        let mut res = vec![f64::NEG_INFINITY; num_trials as usize + 1];
+        res[0] = 0.0;
+        res
+    } else {
+        let num_possible_matches = (1..=num_trials).scan(0.0, |sum, i| {
+            *sum += ((num_intersections as u64 + i - 1) as f64 / i as f64).ln();
+            Some(*sum)
+        });
+        let impossible_init = ln_binomial(
+            total_num_k_mers - num_intersections as u64 + num_trials - 1,
+            num_trials,
+        );
+        let num_impossible_matches = (1..num_trials)
+            .scan(impossible_init, |sum, i| {
+                *sum -= ((total_num_k_mers - num_intersections as u64 + num_trials - i) as f64
+                    / (num_trials - i + 1) as f64)
+                    .ln();
+                Some(*sum)
+            })
+            .chain([0.0]);
+        [impossible_init - num_possible_kmer_sets]
+            .into_iter()
+            .chain(
+                num_possible_matches
+                    .zip_eq(num_impossible_matches)
+                    .map(|(p, i)| p + i - num_possible_kmer_sets),
+            )
+            .collect_vec()
+    }
+}
+
+/// Denominators `∏_{k≠j}(x_j − x_k)` for each interpolation node `x_j`.
+fn lagrange_denominators(xs: &[f64]) -> Vec<f64> {
+    xs.iter()
+        .enumerate()
+        .map(|(j, &xj)| {
+            xs.iter()
+                .enumerate()
+                .filter(|&(k, _)| k != j)
+                .map(|(_, &xk)| xj - xk)
+                .product()
+        })
+        .collect()
+}
+
+/// Builds the ascending-degree coefficients of the degree-`xs.len() - 1`
+/// polynomial interpolating `(xs[j], ys[j])`: for each node `j`, the running
+/// product polynomial `∏_{k≠j}(x − x_k)` is built one linear factor at a
+/// time and accumulated into the result, scaled by `ys[j] / denom[j]`.
+fn lagrange_poly_coeffs(xs: &[f64], ys: &[f64]) -> Vec<f64> {
+    let denoms = lagrange_denominators(xs);
+    let mut coeffs = vec![0.0_f64; xs.len()];
+    for (j, (&xj_y, &denom)) in ys.iter().zip(&denoms).enumerate() {
+        let mut term = vec![1.0_f64];
+        for (k, &xk) in xs.iter().enumerate() {
+            if k == j {
+                continue;
+            }
+            let mut next = vec![0.0_f64; term.len() + 1];
+            for (p, &c) in term.iter().enumerate() {
+                next[p + 1] += c;
+                next[p] -= c * xk;
+            }
+            term = next;
+        }
+        let scale = xj_y / denom;
+        for (c, t) in coeffs.iter_mut().zip(&term) {
+            *c += scale * t;
+        }
+    }
+    coeffs
+}
+
+fn eval_poly(coeffs: &[f64], x: f64) -> f64 {
+    coeffs.iter().rev().fold(0.0, |acc, &c| acc.mul_add(x, c))
+}
+
+/// Fast path for [`iterative_pmfs_ln`]: exactly computes the PMF only at a
+/// sparse grid of `LAGRANGE_GRID_POINTS` intersection sizes and recovers the
+/// rest from the interpolating polynomial, one polynomial per trial-count
+/// index (fit in linear probability space, where the curve stays smooth and
+/// well away from the `-inf` sentinels used near the PMF's support edges).
+fn iterative_pmfs_ln_lagrange(
+    total_num_k_mers: u64,
+    num_trials: u64,
+    distinct_sizes: &[u16],
     num_possible_kmer_sets: f64,
 ) -> Vec<(u16, Vec<f64>)> {
-    intersection_sizes
+    let mut interior: Vec<u16> = distinct_sizes
         .iter()
-        .map(|(&num_intersections, _)| {
-            if num_intersections as u64 == total_num_k_mers {
-                let mut res = vec![f64::NEG_INFINITY; num_trials as usize + 1];
-                res[num_trials as usize] = 0.0;
-                (num_intersections, res)
-            } else if num_intersections == 0 {
-                let mut res = vec![f64::NEG_INFINITY; num_trials as usize + 1];
-                res[0] = 0.0;
-                (num_intersections, res)
-            } else {
-                let num_possible_matches = (1..=num_trials).scan(0.0, |sum, i| {
-                    *sum += ((num_intersections as u64 + i - 1) as f64 / i as f64).ln();
-                    Some(*sum)
-                });
-                let impossible_init = ln_binomial(
-                    total_num_k_mers - num_intersections as u64 + num_trials - 1,
-                    num_trials,
-                );
-                let num_impossible_matches = (1..num_trials)
-                    .scan(impossible_init, |sum, i| {
-                        *sum -= ((total_num_k_mers - num_intersections as u64 + num_trials - i)
-                            as f64
-                            / (num_trials - i + 1) as f64)
-                            .ln();
-                        Some(*sum)
-                    })
-                    .chain([0.0]);
+        .copied()
+        .filter(|&n| n != 0 && u64::from(n) != total_num_k_mers)
+        .collect();
+    interior.sort_unstable();
+    if interior.len() < LAGRANGE_GRID_POINTS {
+        return distinct_sizes
+            .iter()
+            .map(|&n| {
                 (
-                    num_intersections,
-                    [impossible_init - num_possible_kmer_sets]
-                        .into_iter()
-                        .chain(
-                            num_possible_matches
-                                .zip_eq(num_impossible_matches)
-                                .map(|(p, i)| p + i - num_possible_kmer_sets),
-                        )
-                        .collect_vec(),
+                    n,
+                    exact_pmf_ln(total_num_k_mers, num_trials, n, num_possible_kmer_sets),
                 )
+            })
+            .collect();
+    }
+    let last = interior.len() - 1;
+    // `interior` holds distinct sorted sizes and `last >= LAGRANGE_GRID_POINTS - 1`,
+    // so these indices are strictly increasing and end at `last`.
+    let grid_sizes: Vec<u16> = (0..LAGRANGE_GRID_POINTS)
+        .map(|i| interior[i * last / (LAGRANGE_GRID_POINTS - 1)])
+        .collect();
+    let grid_pmfs: Vec<Vec<f64>> = grid_sizes
+        .iter()
+        .map(|&n| exact_pmf_ln(total_num_k_mers, num_trials, n, num_possible_kmer_sets))
+        .collect_vec();
+    let xs: Vec<f64> = grid_sizes.iter().map(|&n| f64::from(n)).collect();
+    let polys: Vec<Vec<f64>> = (0..=num_trials as usize)
+        .map(|i| {
+            let ys: Vec<f64> = grid_pmfs.iter().map(|pmf| pmf[i].exp()).collect();
+            lagrange_poly_coeffs(&xs, &ys)
+        })
+        .collect();
+    distinct_sizes
+        .iter()
+        .map(|&n| {
+            if n == 0 || u64::from(n) == total_num_k_mers {
+                (
+                    n,
+                    exact_pmf_ln(total_num_k_mers, num_trials, n, num_possible_kmer_sets),
+                )
+            } else if let Some(grid_idx) = grid_sizes.iter().position(|&g| g == n) {
+                (n, grid_pmfs[grid_idx].clone())
+            } else {
+                let x = f64::from(n);
+                let pmf = polys
+                    .iter()
+                    .map(|coeffs| eval_poly(coeffs, x).max(0.0).ln())
+                    .collect_vec();
+                (n, pmf)
             }
         })
         .collect()
 }
 
+fn iterative_pmfs_ln(
+    total_num_k_mers: u64,
+    num_trials: u64,
+    intersection_sizes: &HashMap<u16, usize>,
+    num_possible_kmer_sets: f64,
+) -> Vec<(u16, Vec<f64>)> {
+    let distinct_sizes = intersection_sizes.keys().copied().collect_vec();
+    if distinct_sizes.len() >= LAGRANGE_FAST_PATH_MIN_DISTINCT_SIZES {
+        return iterative_pmfs_ln_lagrange(
+            total_num_k_mers,
+            num_trials,
+            &distinct_sizes,
+            num_possible_kmer_sets,
+        );
+    }
+    distinct_sizes
+        .into_iter()
+        .map(|num_intersections| {
+            (
+                num_intersections,
+                exact_pmf_ln(
+                    total_num_k_mers,
+                    num_trials,
+                    num_intersections,
+                    num_possible_kmer_sets,
+                ),
+            )
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use ahash::HashMap;
@@ -177,7 +330,7 @@ mod tests {
 
     use crate::prob::iterative_pmfs_ln;
 
-    use super::highest_hit_prob_per_reference;
+    use super::{exact_pmf_ln, highest_hit_prob_per_reference, iterative_pmfs_ln_lagrange};
 
     fn pmf(
         total_num_k_mers: u64,
@@ -228,9 +381,37 @@ mod tests {
 
     #[test]
     fn test_hit_prob() {
-        let probs = highest_hit_prob_per_reference(400, 200, &(0..=400).collect_vec());
+        let (probs, _) = highest_hit_prob_per_reference(400, 200, &(0..=400).collect_vec());
         dbg!(&probs);
         assert_almost_eq!(probs.iter().sum::<f64>(), 1.0, 1e-7);
         assert!(probs.windows(2).all(|w| w[0] <= w[1]));
     }
+
+    #[test]
+    fn test_lagrange_fast_path_matches_exact_pmf() {
+        let total_num_k_mers = 300;
+        let num_trials = 64;
+        let num_possible_kmer_sets = ln_binomial(total_num_k_mers + num_trials - 1, num_trials);
+        // Many distinct, densely spaced intersection sizes: enough to cross
+        // the fast-path threshold but none at the 0/total_num_k_mers edges.
+        let distinct_sizes: Vec<u16> = (1..=60u16).map(|n| n * 4).collect();
+        let lagrange_pmfs = iterative_pmfs_ln_lagrange(
+            total_num_k_mers,
+            num_trials,
+            &distinct_sizes,
+            num_possible_kmer_sets,
+        );
+        for (num_intersections, approx_pmf) in lagrange_pmfs {
+            let exact_pmf = exact_pmf_ln(
+                total_num_k_mers,
+                num_trials,
+                num_intersections,
+                num_possible_kmer_sets,
+            );
+            assert_almost_eq!(approx_pmf.iter().map(|p| p.exp()).sum::<f64>(), 1.0, 1e-3);
+            approx_pmf.iter().zip(exact_pmf).for_each(|(&a, e)| {
+                assert_almost_eq!(a.exp(), e.exp(), 1e-3);
+            });
+        }
+    }
 }
@@ -1,22 +1,52 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 
+use crate::hll::HyperLogLog;
 use crate::lineage;
-use crate::tree::Tree;
+use crate::tree::{KmerIndex, Tree};
+use crate::utils::Strand;
 use crate::{prob, utils};
+use crossbeam::channel::Sender;
 use indicatif::{ProgressBar, ProgressStyle};
 use itertools::Itertools;
 use log::{info, log_enabled, warn, Level};
 use logging_timer::{time, timer};
 use rayon::prelude::*;
 
+/// Runs every query against `tree` and streams each one's formatted output
+/// line(s) to `sender` as soon as they're ready, rather than collecting
+/// everything into memory first. Returns the query labels that were
+/// actually sent, in no particular order, so the caller can persist exactly
+/// those as checkpointed progress.
+///
+/// `kmer_index` is queried for k-mer posting lists in place of
+/// `tree.k_mer_map`, so callers can point classification at an on-disk
+/// backend (e.g. [`crate::kv_index::KvKmerIndex`]) without loading the
+/// whole posting-list map into memory.
+///
+/// Chunk boundaries (see `utils::chunk_queries_by_residues`) are also the
+/// granularity at which `cancelled` is checked: once it's set, chunks that
+/// haven't started yet are skipped so no new work is dispatched, while a
+/// chunk already in flight still runs to completion (and its queries still
+/// count as sent) rather than being torn down mid-write.
 #[time("info")]
-pub fn raxtax<'a, 'b>(
-    queries: &'b Vec<(String, Vec<u8>)>,
-    tree: &'a Tree,
+#[allow(clippy::too_many_arguments)]
+pub fn raxtax(
+    queries: &[(String, Vec<u8>)],
+    tree: &Tree,
+    kmer_index: &dyn KmerIndex,
     skip_exact_matches: bool,
     raw_confidence: bool,
-    chunk_size: usize,
-) -> Vec<Vec<lineage::EvaluationResult<'a, 'b>>> {
+    both_strands: bool,
+    detect_strand: bool,
+    max_ambiguity: usize,
+    bloom_theta: f64,
+    chunk_sizes: &[usize],
+    sender: &Sender<(String, String, Option<String>)>,
+    tsv: bool,
+    cancelled: &AtomicBool,
+) -> Vec<String> {
+    let scale = tree.scale;
     let warnings = std::sync::Mutex::new(false);
     let empty_vec = Vec::new();
     let pb = ProgressBar::new(queries.len() as u64)
@@ -29,9 +59,26 @@ pub fn raxtax<'a, 'b>(
         )
         .with_message("Running Queries...");
     pb.enable_steady_tick(Duration::from_millis(100));
-    let results = queries
-        .par_chunks(chunk_size)
+    // Chunk boundaries are precomputed by the caller (see
+    // `utils::chunk_queries_by_residues`) so chunks can be balanced by total
+    // residue count rather than query count: k-mer extraction and scoring
+    // cost scales with residues, so fixed-size chunks let a few very long
+    // queries starve the rayon workers that drew them.
+    let mut offset = 0;
+    let query_chunks = chunk_sizes
+        .iter()
+        .map(|&size| {
+            let chunk = &queries[offset..offset + size];
+            offset += size;
+            chunk
+        })
+        .collect_vec();
+    let sent_queries = query_chunks
+        .into_par_iter()
         .flat_map(|q| {
+            if cancelled.load(Ordering::Relaxed) {
+                return Vec::new();
+            }
             let mut intersect_buffer: Vec<u16> = vec![0; tree.num_tips];
             q.iter().map(|(query_label, query_sequence)| {
                 pb.inc(1);
@@ -49,43 +96,280 @@ pub fn raxtax<'a, 'b>(
                     }
                 }
                 let tmr = timer!(Level::Debug; "K-mer Intersections");
-                let k_mers = utils::sequence_to_kmers(query_sequence);
-                assert!(u16::try_from(k_mers.len()).is_ok());
-                let num_trials = k_mers.len() / 2;
-                for query_kmer in &k_mers {
-                        tree.k_mer_map[*query_kmer as usize]
-                            .iter()
-                            .for_each(|sequence_id| {
-                                unsafe { *intersect_buffer.get_unchecked_mut(*sequence_id as usize) += 1 };
-                            });
+                // Scores one orientation's k-mer set against every reference,
+                // returning the per-reference highest-hit probabilities
+                // together with their raw (pre-normalization) sum, which
+                // `--detect-strand` uses as a goodness-of-fit proxy to pick
+                // between orientations below.
+                let score_orientation = |k_mers: &[u32], intersect_buffer: &mut Vec<u16>| {
+                    intersect_buffer.fill(0);
+                    let num_trials = k_mers.len() / 2;
+                    // When a bloom prefilter is present, references whose filter
+                    // doesn't report enough of the query's k-mers are zeroed out
+                    // before the probability model below sees them. The k-mer
+                    // posting lists are indexed by k-mer rather than by
+                    // reference, so this still touches every posting, but it
+                    // keeps the union-of-filters pruning guarantee: a reference
+                    // only survives if its own leaf filter and every
+                    // ancestor's filter pass the threshold.
+                    let bloom_candidates = tree
+                        .bloom_index
+                        .as_ref()
+                        .map(|sbt| sbt.candidate_sequences(k_mers, bloom_theta));
+                    if let (Some(hll_precision), Some(hll_index)) =
+                        (tree.hll_precision, tree.hll_index.as_ref())
+                    {
+                        // Estimate intersections from HyperLogLog sketches via
+                        // inclusion-exclusion instead of exact posting-list
+                        // counting: |A ∩ B| = |A| + |B| - |A ∪ B|.
+                        let mut query_hll = HyperLogLog::new(hll_precision);
+                        for &k_mer in k_mers {
+                            query_hll.insert_hash(utils::hash_kmer(k_mer));
+                        }
+                        let query_cardinality = query_hll.estimate();
+                        let candidate_ids: Vec<usize> = match &bloom_candidates {
+                            Some(candidates) => candidates.iter().map(|&idx| idx as usize).collect(),
+                            None => (0..tree.num_tips).collect(),
+                        };
+                        for idx in candidate_ids {
+                            let reference_hll = &hll_index[idx];
+                            let union_cardinality = query_hll.union(reference_hll).estimate();
+                            let intersection = (query_cardinality + reference_hll.estimate()
+                                - union_cardinality)
+                                .clamp(0.0, k_mers.len() as f64);
+                            intersect_buffer[idx] = intersection.round() as u16;
+                        }
+                    } else if let Some(candidates) = &bloom_candidates {
+                        // Only the candidates' counters are incremented. Every
+                        // posting of every query k-mer is still visited, since
+                        // posting lists are indexed by k-mer, so the prefilter
+                        // does not reduce the number of postings this loop reads.
+                        let mut is_candidate = vec![false; tree.num_tips];
+                        for &idx in candidates {
+                            is_candidate[idx as usize] = true;
+                        }
+                        for query_kmer in k_mers {
+                            kmer_index.postings(*query_kmer)
+                                .iter()
+                                .for_each(|sequence_id| {
+                                    if is_candidate[*sequence_id as usize] {
+                                        unsafe { *intersect_buffer.get_unchecked_mut(*sequence_id as usize) += 1 };
+                                    }
+                                });
+                        }
+                    } else {
+                        for query_kmer in k_mers {
+                            kmer_index.postings(*query_kmer)
+                                .iter()
+                                .for_each(|sequence_id| {
+                                    unsafe { *intersect_buffer.get_unchecked_mut(*sequence_id as usize) += 1 };
+                                });
+                        }
+                    }
+                    if skip_exact_matches {
+                        // look for the next best match
+                        for &id in exact_matches { unsafe { *intersect_buffer.get_unchecked_mut(id as usize) = 0 } }
+                    }
+                    prob::highest_hit_prob_per_reference(k_mers.len() as u16, num_trials, intersect_buffer)
+                };
+
+                let mut k_mers = utils::sequence_to_kmers(query_sequence, tree.k, max_ambiguity);
+                if both_strands {
+                    // Merges in the reverse-complement k-mers, roughly
+                    // doubling the per-query work below. Distinct from
+                    // --detect-strand, which scores each orientation
+                    // separately and keeps the better-fitting one.
+                    let rc_k_mers = k_mers
+                        .iter()
+                        .map(|&k_mer| utils::reverse_complement_kmer(k_mer, tree.k))
+                        .collect_vec();
+                    k_mers.extend(rc_k_mers);
+                    k_mers.sort_unstable();
+                    k_mers.dedup();
+                }
+                // Derived from the forward k-mers before the scale filter
+                // below, so each orientation's FracMinHash membership is
+                // decided on its own hash rather than inherited from
+                // whichever forward k-mers happened to pass the filter:
+                // `in_scaled_sketch(k)` and `in_scaled_sketch(rc(k))` are
+                // unrelated values.
+                let mut rc_k_mers_for_detection = detect_strand.then(|| {
+                    k_mers
+                        .iter()
+                        .map(|&k_mer| utils::reverse_complement_kmer(k_mer, tree.k))
+                        .collect_vec()
+                });
+                if scale > 1 {
+                    // Keep only the query's own FracMinHash sketch, so the
+                    // intersection counts below stay unbiased estimators of
+                    // the true intersection against the (equally sketched)
+                    // reference k-mer sets.
+                    k_mers.retain(|&k_mer| utils::in_scaled_sketch(k_mer, scale));
+                    if let Some(rc_k_mers) = &mut rc_k_mers_for_detection {
+                        rc_k_mers.retain(|&k_mer| utils::in_scaled_sketch(k_mer, scale));
                     }
-                if skip_exact_matches {
-                    // look for the next best match
-                    for &id in exact_matches { unsafe { *intersect_buffer.get_unchecked_mut(id as usize) = 0 } }
                 }
+                assert!(u16::try_from(k_mers.len()).is_ok());
+
+                let (highest_hit_probs, strand) = if let Some(rc_k_mers) = rc_k_mers_for_detection {
+                    let (fwd_probs, fwd_sum) = score_orientation(&k_mers, &mut intersect_buffer);
+                    let (rc_probs, rc_sum) = score_orientation(&rc_k_mers, &mut intersect_buffer);
+                    if rc_sum > fwd_sum {
+                        (rc_probs, Strand::ReverseComplement)
+                    } else {
+                        (fwd_probs, Strand::Forward)
+                    }
+                } else {
+                    let (probs, _) = score_orientation(&k_mers, &mut intersect_buffer);
+                    (probs, Strand::Forward)
+                };
                 drop(tmr);
-                let highest_hit_probs = prob::highest_hit_prob_per_reference(k_mers.len() as u16, num_trials, &intersect_buffer);
-                let eval_res = lineage::Lineage::new(query_label, tree, highest_hit_probs).evaluate();
+                let eval_res = lineage::Lineage::new(query_label, tree, highest_hit_probs, strand).evaluate();
                 assert!(!eval_res.is_empty());
-                if !raw_confidence && !skip_exact_matches {
+                let eval_res = if !raw_confidence && !skip_exact_matches {
                     // Special case: if there is exactly 1 exact match, confidence is set to 1.0
                     if let [idx] = exact_matches[..] {
-                        return vec![lineage::EvaluationResult {
+                        vec![lineage::EvaluationResult {
                             query_label,
                             lineage: &tree.lineages[idx as usize],
                             confidence_values: vec![1.0; tree.lineages[idx as usize].chars().filter(|c| *c == ',').count() + 1],
                             local_signal: eval_res[0].local_signal,
-                            global_signal: eval_res[0].global_signal
-                        }];
+                            global_signal: eval_res[0].global_signal,
+                            strand: eval_res[0].strand,
+                        }]
+                    } else {
+                        eval_res
                     }
-                }
-                eval_res
+                } else {
+                    eval_res
+                };
+                let show_strand = both_strands || detect_strand;
+                let results_string = eval_res
+                    .iter()
+                    .map(|er| er.get_output_string(show_strand))
+                    .join("\n");
+                let tsv_string = tsv.then(|| {
+                    let sequence = utils::decompress_sequence(query_sequence);
+                    eval_res
+                        .iter()
+                        .map(|er| er.get_tsv_string(&sequence, show_strand))
+                        .join("\n")
+                });
+                let _ = sender.send((query_label.clone(), results_string, tsv_string));
+                query_label.clone()
             }).collect_vec()
         })
-        .collect::<Vec<Vec<lineage::EvaluationResult<'a, 'b>>>>();
+        .collect::<Vec<String>>();
 
     if *warnings.lock().unwrap() && log_enabled!(Level::Warn) {
         eprintln!("\x1b[33m[WARN ]\x1b[0m Exact matches for some queries differ above the species level! Check the log file for more information!");
     }
-    results
+    sent_queries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::raxtax;
+    use crate::parser;
+    use crate::utils::{DEFAULT_BLOOM_THETA, DEFAULT_MAX_AMBIGUITY};
+    use crossbeam::channel::unbounded;
+
+    fn reverse_complement(sequence: &str) -> String {
+        sequence
+            .chars()
+            .rev()
+            .map(|c| match c.to_ascii_uppercase() {
+                'A' => 'T',
+                'T' => 'A',
+                'C' => 'G',
+                'G' => 'C',
+                other => other,
+            })
+            .collect()
+    }
+
+    fn classify_one(
+        reference_seq: &str,
+        query_seq: &str,
+        scale: u64,
+        both_strands: bool,
+        detect_strand: bool,
+    ) -> String {
+        let fasta_str = format!(">a;tax=p:P1,c:C1,o:O1,f:F1,g:G1,s:S1;\n{reference_seq}\n");
+        let tree =
+            parser::parse_reference_fasta_str(&fasta_str, 8, scale, false, None, DEFAULT_MAX_AMBIGUITY)
+                .unwrap();
+        let query_fasta = format!(">q;\n{query_seq}\n");
+        let queries = parser::parse_query_fasta_str(&query_fasta).unwrap();
+        let (sender, receiver) = unbounded();
+        let cancelled = std::sync::atomic::AtomicBool::new(false);
+        raxtax(
+            &queries,
+            &tree,
+            &tree.k_mer_map,
+            false,
+            true,
+            both_strands,
+            detect_strand,
+            DEFAULT_MAX_AMBIGUITY,
+            DEFAULT_BLOOM_THETA,
+            &[queries.len()],
+            &sender,
+            false,
+            &cancelled,
+        );
+        drop(sender);
+        receiver.recv().unwrap().1
+    }
+
+    #[test]
+    fn test_detect_strand_picks_reverse_complement_for_rc_query() {
+        let reference_seq = "ACGTACGGTTCAGGTCAATGCCGATTACGGTATCAGC";
+        let query_seq = reverse_complement(reference_seq);
+        let results = classify_one(reference_seq, &query_seq, 1, false, true);
+        assert!(
+            results.ends_with('-'),
+            "expected reverse-complement strand, got: {results}"
+        );
+    }
+
+    #[test]
+    fn test_detect_strand_with_scaled_sketch_still_picks_reverse_complement() {
+        // Regression test: rc_k_mers must be filtered by `in_scaled_sketch`
+        // on its own hash rather than inherited from whichever forward
+        // k-mers passed the filter first, or this keeps picking Forward
+        // regardless of which orientation the query actually matches.
+        let reference_seq = "ACGTACGGTTCAGGTCAATGCCGATTACGGTATCAGC";
+        let query_seq = reverse_complement(reference_seq);
+        for &scale in &[2_u64, 4] {
+            let results = classify_one(reference_seq, &query_seq, scale, false, true);
+            assert!(
+                results.ends_with('-'),
+                "scale={scale}: expected reverse-complement strand, got: {results}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_both_strands_always_reports_forward() {
+        // --both-strands merges both orientations' k-mers into one scoring
+        // pass rather than picking a winner, so the reported strand is
+        // always Forward even when the query only matches in its rc
+        // orientation; the column is still shown because --both-strands,
+        // like --detect-strand, opts into it.
+        let reference_seq = "ACGTACGGTTCAGGTCAATGCCGATTACGGTATCAGC";
+        let query_seq = reverse_complement(reference_seq);
+        let results = classify_one(reference_seq, &query_seq, 2, true, false);
+        assert!(results.ends_with('+'));
+    }
+
+    #[test]
+    fn test_default_forward_only_output_has_no_strand_column() {
+        let reference_seq = "ACGTACGGTTCAGGTCAATGCCGATTACGGTATCAGC";
+        let results = classify_one(reference_seq, reference_seq, 1, false, false);
+        // Neither --both-strands nor --detect-strand requested: the
+        // trailing strand column must not be appended, preserving the
+        // default output schema downstream parsers rely on.
+        assert!(!results.ends_with('-') && !results.ends_with('+'));
+    }
 }
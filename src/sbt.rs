@@ -0,0 +1,199 @@
+//! Sequence Bloom Tree: a hierarchical bloom-filter index used to prune
+//! references that share no (or few) k-mers with a query before the exact
+//! intersection counts in [`crate::raxtax::raxtax`] are computed.
+//!
+//! Each leaf holds a bloom filter of one reference sequence's k-mer set;
+//! each internal node stores the bitwise-OR union of its children's
+//! filters. Because a parent's filter reports "present" for every k-mer that
+//! any of its children reports "present" for, a subtree's present count is
+//! never below that of any leaf under it, so pruning a subtree whose
+//! present-fraction falls below `theta` never discards a leaf that would
+//! have passed on its own.
+
+use std::hash::{Hash, Hasher};
+
+use serde::{Deserialize, Serialize};
+
+/// Number of independent hash functions used per inserted/queried k-mer.
+const NUM_HASH_FUNCTIONS: usize = 4;
+
+/// Bits per reference sequence in a leaf filter.
+pub const DEFAULT_BITS_PER_LEAF: usize = 2048;
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: usize,
+}
+
+impl BloomFilter {
+    fn new(num_bits: usize) -> Self {
+        Self {
+            bits: vec![0_u64; num_bits.div_ceil(64)],
+            num_bits,
+        }
+    }
+
+    fn hash_indices(&self, k_mer: u32) -> [usize; NUM_HASH_FUNCTIONS] {
+        std::array::from_fn(|i| {
+            let mut hasher = ahash::AHasher::default();
+            (k_mer, i).hash(&mut hasher);
+            (hasher.finish() as usize) % self.num_bits
+        })
+    }
+
+    fn insert(&mut self, k_mer: u32) {
+        for idx in self.hash_indices(k_mer) {
+            self.bits[idx / 64] |= 1 << (idx % 64);
+        }
+    }
+
+    fn contains(&self, k_mer: u32) -> bool {
+        self.hash_indices(k_mer)
+            .into_iter()
+            .all(|idx| self.bits[idx / 64] & (1 << (idx % 64)) != 0)
+    }
+
+    fn union_with(&mut self, other: &BloomFilter) {
+        for (a, b) in self.bits.iter_mut().zip(&other.bits) {
+            *a |= b;
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+enum SbtNode {
+    Leaf {
+        sequence_idx: u32,
+        filter: BloomFilter,
+    },
+    Internal {
+        filter: BloomFilter,
+        children: Vec<SbtNode>,
+    },
+}
+
+impl SbtNode {
+    fn filter(&self) -> &BloomFilter {
+        match self {
+            Self::Leaf { filter, .. } | Self::Internal { filter, .. } => filter,
+        }
+    }
+
+    fn collect_candidates(&self, query_k_mers: &[u32], theta: f64, out: &mut Vec<u32>) {
+        let present = query_k_mers
+            .iter()
+            .filter(|&&k_mer| self.filter().contains(k_mer))
+            .count();
+        if (present as f64) < theta * query_k_mers.len() as f64 {
+            return;
+        }
+        match self {
+            Self::Leaf { sequence_idx, .. } => out.push(*sequence_idx),
+            Self::Internal { children, .. } => {
+                for child in children {
+                    child.collect_candidates(query_k_mers, theta, out);
+                }
+            }
+        }
+    }
+}
+
+/// A Sequence Bloom Tree over a reference database's k-mer sets, used to
+/// prune references before the exact intersection counting in
+/// [`crate::raxtax::raxtax`].
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SequenceBloomTree {
+    root: SbtNode,
+}
+
+impl SequenceBloomTree {
+    /// Builds the tree from each reference's already-extracted k-mer set,
+    /// indexed identically to [`crate::tree::KmerMap`]'s posting lists.
+    pub fn build(sequence_k_mers: &[Vec<u32>], bits_per_leaf: usize) -> Self {
+        let leaves: Vec<SbtNode> = sequence_k_mers
+            .iter()
+            .enumerate()
+            .map(|(idx, k_mers)| {
+                let mut filter = BloomFilter::new(bits_per_leaf);
+                for &k_mer in k_mers {
+                    filter.insert(k_mer);
+                }
+                SbtNode::Leaf {
+                    sequence_idx: idx as u32,
+                    filter,
+                }
+            })
+            .collect();
+        Self {
+            root: Self::build_level(leaves),
+        }
+    }
+
+    fn build_level(nodes: Vec<SbtNode>) -> SbtNode {
+        if nodes.len() == 1 {
+            return nodes.into_iter().next().unwrap();
+        }
+        let mut next = Vec::with_capacity(nodes.len().div_ceil(2));
+        let mut iter = nodes.into_iter();
+        while let Some(first) = iter.next() {
+            if let Some(second) = iter.next() {
+                let mut filter = first.filter().clone();
+                filter.union_with(second.filter());
+                next.push(SbtNode::Internal {
+                    filter,
+                    children: vec![first, second],
+                });
+            } else {
+                next.push(first);
+            }
+        }
+        Self::build_level(next)
+    }
+
+    /// Descends from the root, pruning any subtree whose present-fraction
+    /// falls below `theta`, and returns the sequence indices of the
+    /// surviving leaves.
+    pub fn candidate_sequences(&self, query_k_mers: &[u32], theta: f64) -> Vec<u32> {
+        let mut out = Vec::new();
+        self.root.collect_candidates(query_k_mers, theta, &mut out);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter_round_trip() {
+        let mut filter = BloomFilter::new(DEFAULT_BITS_PER_LEAF);
+        filter.insert(0b1010_1010);
+        filter.insert(0b0101_0101);
+        assert!(filter.contains(0b1010_1010));
+        assert!(filter.contains(0b0101_0101));
+    }
+
+    #[test]
+    fn test_sbt_prunes_unrelated_sequences() {
+        let sequence_k_mers = vec![
+            vec![1, 2, 3, 4],
+            vec![100, 101, 102, 103],
+            vec![1, 2, 3, 5],
+        ];
+        let sbt = SequenceBloomTree::build(&sequence_k_mers, DEFAULT_BITS_PER_LEAF);
+        let query = vec![1, 2, 3, 4];
+        let mut candidates = sbt.candidate_sequences(&query, 0.5);
+        candidates.sort_unstable();
+        assert_eq!(candidates, vec![0, 2]);
+    }
+
+    #[test]
+    fn test_sbt_theta_zero_keeps_everything() {
+        let sequence_k_mers = vec![vec![1, 2], vec![3, 4]];
+        let sbt = SequenceBloomTree::build(&sequence_k_mers, DEFAULT_BITS_PER_LEAF);
+        let mut candidates = sbt.candidate_sequences(&[9, 9, 9], 0.0);
+        candidates.sort_unstable();
+        assert_eq!(candidates, vec![0, 1]);
+    }
+}
@@ -0,0 +1,207 @@
+//! `raxtax serve`: parses the reference database once, keeps the resulting
+//! [`Tree`] resident in memory, and answers classification requests over a
+//! small HTTP API. This avoids paying the reference-parsing/index-build cost
+//! on every invocation, which dominates runtime for small query batches when
+//! running classify mode repeatedly.
+//!
+//! Routes:
+//! - `GET /healthz` — liveness probe, always responds `200 OK`.
+//! - `POST /classify` — body is a raw query FASTA; response is the primary
+//!   output followed by a `\n---TSV---\n` separator and the TSV output, the
+//!   same two outputs classify mode (with `--tsv`) writes to `raxtax.out`
+//!   and `raxtax.tsv` in its output directory.
+
+use std::io::Read;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::builder::TypedValueParser;
+use clap::Args;
+use tiny_http::{Method, Response, Server};
+
+use crate::kv_index::KvKmerIndex;
+use crate::raxtax::raxtax;
+use crate::tree::KmerIndex;
+use crate::{hll, parser, tree::Tree, utils};
+
+#[derive(Args)]
+pub struct ServeArgs {
+    /// Path to the reference FASTA or cached binary database
+    #[arg(long)]
+    pub database: PathBuf,
+    /// Length of the k-mers used for database and query matching
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u8).range(8..=16).map(usize::from))]
+    pub kmer_size: usize,
+    /// Also match k-mers from the reverse complement strand
+    #[arg(long, conflicts_with = "detect_strand")]
+    pub both_strands: bool,
+    /// Classify both orientations separately and report whichever one fits
+    /// the model better
+    #[arg(long, conflicts_with = "both_strands")]
+    pub detect_strand: bool,
+    /// Skip k-mer windows overlapping an IUPAC ambiguity code if they would
+    /// expand into more than this many concrete k-mers
+    #[arg(long, default_value_t = utils::DEFAULT_MAX_AMBIGUITY)]
+    pub max_ambiguity: usize,
+    /// FracMinHash scaling factor; 1 disables sketching
+    #[arg(long, default_value_t = utils::DEFAULT_SCALE)]
+    pub scaled: u64,
+    /// Build a Sequence Bloom Tree prefilter over the reference k-mers
+    #[arg(long)]
+    pub bloom_prefilter: bool,
+    /// Bloom prefilter present-fraction threshold
+    #[arg(long, default_value_t = utils::DEFAULT_BLOOM_THETA)]
+    pub bloom_theta: f64,
+    /// Use HyperLogLog intersection estimation instead of exact posting-list
+    /// counting
+    #[arg(long)]
+    pub hll: bool,
+    /// HyperLogLog precision p (m = 2^p registers)
+    #[arg(long, default_value_t = hll::DEFAULT_HLL_PRECISION, value_parser = clap::value_parser!(u8).range((hll::MIN_HLL_PRECISION as i64)..=(hll::MAX_HLL_PRECISION as i64)).map(usize::from))]
+    pub hll_precision: usize,
+    /// Look up k-mer posting lists in a `raxtax index build-kv` sidecar
+    /// through a memory map instead of the in-memory k-mer map. The
+    /// reference database, including its own k-mer map, is still loaded
+    /// into RAM in full
+    #[arg(long)]
+    pub kv_index: Option<PathBuf>,
+    /// Skip a query's exact sequence matches and report the next best match
+    #[arg(long)]
+    pub skip_exact_matches: bool,
+    /// Don't adjust confidence values for 1 exact match
+    #[arg(long)]
+    pub raw_confidence: bool,
+    /// Number of threads; if 0, uses all available threads
+    #[arg(short, long, default_value_t = 0)]
+    pub threads: usize,
+    /// Target total residue count per query chunk; overrides the size
+    /// automatically derived from thread count. Ignored with a single
+    /// thread or when all queries have the same length
+    #[arg(long)]
+    pub chunk_residues: Option<usize>,
+    /// Host to bind the HTTP server to
+    #[arg(long, default_value = "127.0.0.1")]
+    pub host: String,
+    /// Port to bind the HTTP server to
+    #[arg(long, default_value_t = 8080)]
+    pub port: u16,
+}
+
+fn respond_text(request: tiny_http::Request, status_code: u16, body: String) {
+    let response = Response::from_string(body)
+        .with_status_code(status_code)
+        .with_header(
+            tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"text/plain; charset=utf-8"[..])
+                .unwrap(),
+        );
+    if let Err(e) = request.respond(response) {
+        log::warn!("Failed to write HTTP response: {e}");
+    }
+}
+
+fn classify(
+    tree: &Tree,
+    kmer_index: &dyn KmerIndex,
+    args: &ServeArgs,
+    fasta_str: &str,
+) -> Result<String> {
+    let queries = parser::parse_query_fasta_str(fasta_str).context("Failed to parse query FASTA")?;
+    let n_threads = rayon::current_num_threads();
+    let chunk_sizes = utils::chunk_queries_by_residues(&queries, n_threads, args.chunk_residues);
+    let (sender, receiver) = crossbeam::channel::unbounded();
+    // A single request never outlives its own classification, so there's
+    // nothing for this flag to ever cancel; it only exists because
+    // `raxtax()` checks it between chunks for the long-running classify
+    // mode's SIGINT/SIGTERM handling.
+    let cancelled = std::sync::atomic::AtomicBool::new(false);
+    raxtax(
+        &queries,
+        tree,
+        kmer_index,
+        args.skip_exact_matches,
+        args.raw_confidence,
+        args.both_strands,
+        args.detect_strand,
+        args.max_ambiguity,
+        args.bloom_theta,
+        &chunk_sizes,
+        &sender,
+        true,
+        &cancelled,
+    );
+    drop(sender);
+    let mut primary_lines = Vec::new();
+    let mut tsv_lines = Vec::new();
+    for (_, results, tsv_result) in receiver {
+        primary_lines.push(results);
+        if let Some(tsv_result) = tsv_result {
+            tsv_lines.push(tsv_result);
+        }
+    }
+    Ok(format!(
+        "{}\n---TSV---\n{}",
+        primary_lines.join("\n"),
+        tsv_lines.join("\n")
+    ))
+}
+
+pub fn run(args: &ServeArgs) -> Result<()> {
+    if let Err(e) = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build_global()
+    {
+        log::warn!("Failed to set up a dedicated thread pool, using the default one: {e}");
+    }
+    let (_, tree) = parser::parse_reference_fasta_file(
+        &args.database,
+        args.kmer_size,
+        args.scaled,
+        args.bloom_prefilter,
+        args.hll.then_some(args.hll_precision),
+        args.max_ambiguity,
+    )
+    .with_context(|| format!("Failed to parse {}", args.database.display()))?;
+    log::info!(
+        "Loaded reference database with {} references, serving on {}:{}",
+        tree.num_tips,
+        args.host,
+        args.port
+    );
+
+    // When `--kv-index` points at a `raxtax index build-kv` sidecar, look up
+    // posting lists in its memory map instead of `tree.k_mer_map`; `tree`,
+    // including its k-mer map, is loaded in full either way.
+    let kv_index = args
+        .kv_index
+        .as_ref()
+        .map(|path| {
+            KvKmerIndex::open(path)
+                .with_context(|| format!("Failed to open KV index {}", path.display()))
+        })
+        .transpose()?;
+    let kmer_index: &dyn KmerIndex = kv_index
+        .as_ref()
+        .map_or(&tree.k_mer_map as &dyn KmerIndex, |kv| kv as &dyn KmerIndex);
+
+    let server = Server::http((args.host.as_str(), args.port))
+        .map_err(|e| anyhow::anyhow!("Failed to bind {}:{}: {e}", args.host, args.port))?;
+    for request in server.incoming_requests() {
+        match (request.method(), request.url()) {
+            (Method::Get, "/healthz") => respond_text(request, 200, "OK".to_string()),
+            (Method::Post, "/classify") => {
+                let mut request = request;
+                let mut body = String::new();
+                if let Err(e) = request.as_reader().read_to_string(&mut body) {
+                    respond_text(request, 400, format!("Failed to read request body: {e}"));
+                    continue;
+                }
+                match classify(&tree, kmer_index, args, &body) {
+                    Ok(output) => respond_text(request, 200, output),
+                    Err(e) => respond_text(request, 400, format!("{e:#}")),
+                }
+            }
+            _ => respond_text(request, 404, "Not Found".to_string()),
+        }
+    }
+    Ok(())
+}
@@ -5,7 +5,7 @@ use std::{
     path::PathBuf,
 };
 
-use ahash::HashMap;
+use ahash::{HashMap, HashMapExt};
 use indicatif::{ProgressIterator, ProgressStyle};
 use itertools::Itertools;
 use log::{log_enabled, Level};
@@ -13,13 +13,97 @@ use logging_timer::time;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::utils::map_four_to_two_bit_repr;
+use crate::hll::HyperLogLog;
+use crate::sbt::SequenceBloomTree;
+use crate::utils::MAX_KMER_SIZE;
+
+/// Above this k, a dense `4^k`-sized `Vec` would be too large to allocate
+/// up front, so the posting lists fall back to a hashed sparse map.
+const MAX_DENSE_KMER_SIZE: usize = 12;
+
+/// Maps a packed k-mer to its posting list of database sequence indices,
+/// either as a dense array indexed by the k-mer's integer value (cheap for
+/// small k) or as a hash map (for k too large to allocate densely).
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum KmerMap {
+    Dense(Vec<Vec<IndexType>>),
+    Sparse(HashMap<u32, Vec<IndexType>>),
+}
+
+impl KmerMap {
+    fn new(k: usize) -> Self {
+        if k <= MAX_DENSE_KMER_SIZE {
+            Self::Dense(vec![Vec::new(); 4_usize.pow(k as u32)])
+        } else {
+            Self::Sparse(HashMap::new())
+        }
+    }
+
+    fn push(&mut self, k_mer: u32, idx: IndexType) {
+        match self {
+            Self::Dense(v) => v[k_mer as usize].push(idx),
+            Self::Sparse(m) => m.entry(k_mer).or_default().push(idx),
+        }
+    }
+
+    pub fn get(&self, k_mer: u32) -> &[IndexType] {
+        match self {
+            Self::Dense(v) => v.get(k_mer as usize).map_or(&[], Vec::as_slice),
+            Self::Sparse(m) => m.get(&k_mer).map_or(&[], Vec::as_slice),
+        }
+    }
+
+    /// All k-mers with a non-empty posting list, paired with that list.
+    /// Used to stream `k_mer_map`'s contents into an alternative backend
+    /// (e.g. [`crate::kv_index::KvKmerIndex`]) without holding a second
+    /// copy of every list at once.
+    pub(crate) fn iter(&self) -> Box<dyn Iterator<Item = (u32, &[IndexType])> + '_> {
+        match self {
+            Self::Dense(v) => Box::new(
+                v.iter()
+                    .enumerate()
+                    .filter(|(_, postings)| !postings.is_empty())
+                    .map(|(k_mer, postings)| (k_mer as u32, postings.as_slice())),
+            ),
+            Self::Sparse(m) => Box::new(m.iter().map(|(&k_mer, postings)| (k_mer, postings.as_slice()))),
+        }
+    }
+
+    fn finalize(self) -> Self {
+        match self {
+            Self::Dense(v) => Self::Dense(
+                v.into_par_iter()
+                    .map(|seqs| seqs.into_iter().unique().sorted().collect_vec())
+                    .collect(),
+            ),
+            Self::Sparse(m) => Self::Sparse(
+                m.into_iter()
+                    .map(|(k, seqs)| (k, seqs.into_iter().unique().sorted().collect_vec()))
+                    .collect(),
+            ),
+        }
+    }
+}
 
 #[cfg(feature = "huge_db")]
-type IndexType = usize;
+pub(crate) type IndexType = usize;
 
 #[cfg(not(feature = "huge_db"))]
-type IndexType = u32;
+pub(crate) type IndexType = u32;
+
+/// Common interface for fetching a k-mer's posting list, so query-time code
+/// doesn't need to know whether it's indexing straight into an in-memory
+/// [`KmerMap`] or fetching lazily from an on-disk backend such as
+/// [`crate::kv_index::KvKmerIndex`].
+pub trait KmerIndex {
+    fn postings(&self, k_mer: u32) -> &[IndexType];
+}
+
+impl KmerIndex for KmerMap {
+    fn postings(&self, k_mer: u32) -> &[IndexType] {
+        self.get(k_mer)
+    }
+}
 
 #[cfg(not(feature = "huge_db"))]
 fn check_lineage_size(db_size: usize) {
@@ -38,20 +122,61 @@ pub struct Tree {
     pub root: Node,
     pub lineages: Vec<String>,
     pub sequences: HashMap<Vec<u8>, Vec<IndexType>>,
-    pub k_mer_map: Vec<Vec<IndexType>>,
+    pub k_mer_map: KmerMap,
     pub num_tips: usize,
+    pub k: usize,
+    /// FracMinHash scaling factor used to build `k_mer_map`; `1` means no
+    /// sketching (every k-mer is kept).
+    pub scale: u64,
+    /// Sequence Bloom Tree used to prune references before exact
+    /// intersection counting; `None` when `--bloom-prefilter` is disabled.
+    pub bloom_index: Option<SequenceBloomTree>,
+    /// Precision `p` (`m = 2^p` registers) of `hll_index`'s sketches;
+    /// `None` when `--hll` is disabled, in which case exact per-reference
+    /// k-mer intersection counting via `k_mer_map` is used instead.
+    pub hll_precision: Option<usize>,
+    /// One HyperLogLog sketch per reference sequence, indexed identically
+    /// to `k_mer_map`'s posting lists.
+    pub hll_index: Option<Vec<HyperLogLog>>,
+    /// `--max-ambiguity` cap used to expand IUPAC ambiguity codes while
+    /// building `k_mer_map`, kept so `raxtax index diff` can rebuild a
+    /// merged database under the same cap the base index was built with.
+    pub max_ambiguity: usize,
 }
 
 impl Tree {
     #[time("debug", "Tree::{}")]
-    pub fn new(lineages: Vec<String>, sequences: Vec<Vec<u8>>) -> Result<Self> {
+    pub fn new(
+        lineages: Vec<String>,
+        sequences: Vec<Vec<u8>>,
+        k: usize,
+        scale: u64,
+        bloom_prefilter: bool,
+        hll_precision: Option<usize>,
+        max_ambiguity: usize,
+    ) -> Result<Self> {
+        assert!(
+            (crate::utils::MIN_KMER_SIZE..=MAX_KMER_SIZE).contains(&k),
+            "k-mer size must be between {} and {}",
+            crate::utils::MIN_KMER_SIZE,
+            MAX_KMER_SIZE
+        );
         check_lineage_size(lineages.len());
         let mut root = Node::new(String::from("root"), 0, NodeType::Inner);
         let mut sequence_map: HashMap<Vec<u8>, Vec<IndexType>> =
             sequences.iter().map(|s| (s.clone(), Vec::new())).collect();
-        let mut k_mer_map: Vec<Vec<IndexType>> = vec![Vec::new(); 2 << 15];
+        let mut k_mer_map = KmerMap::new(k);
         let mut lineage_sequence_pairs = lineages.into_iter().zip_eq(sequences).collect_vec();
         lineage_sequence_pairs.sort_by(|(l1, _), (l2, _)| l1.cmp(l2));
+        let mut sequence_k_mers: Vec<Vec<u32>> = if bloom_prefilter {
+            vec![Vec::new(); lineage_sequence_pairs.len()]
+        } else {
+            Vec::new()
+        };
+        let mut hll_registers: Vec<HyperLogLog> = match hll_precision {
+            Some(p) => vec![HyperLogLog::new(p); lineage_sequence_pairs.len()],
+            None => Vec::new(),
+        };
         let mut confidence_idx = 0_usize;
         let _ = lineage_sequence_pairs
             .iter()
@@ -111,31 +236,42 @@ impl Tree {
                     .unwrap()
                     .push(idx as IndexType);
 
-                sequence.windows(8).for_each(|vals| {
-                    if let Some(k_mer) = vals
-                        .iter()
-                        .enumerate()
-                        .map(|(j, v)| map_four_to_two_bit_repr(*v).map(|c| c << (14 - j * 2)))
-                        .fold_options(0_u16, |acc, c| acc | c)
-                    {
-                        k_mer_map[k_mer as usize].push(idx as IndexType);
+                // Expands IUPAC ambiguity codes into the concrete k-mers they
+                // represent, exactly like query k-mer generation does, so
+                // ambiguous reference bases still contribute postings instead
+                // of dropping the whole overlapping window.
+                for k_mer in crate::utils::sequence_to_kmers(sequence, k, max_ambiguity) {
+                    if crate::utils::in_scaled_sketch(k_mer, scale) {
+                        k_mer_map.push(k_mer, idx as IndexType);
+                        if bloom_prefilter {
+                            sequence_k_mers[idx].push(k_mer);
+                        }
+                        if hll_precision.is_some() {
+                            hll_registers[idx].insert_hash(crate::utils::hash_kmer(k_mer));
+                        }
                     }
-                });
+                }
                 Ok(())
             })
             .collect::<Result<Vec<()>>>()?;
         root.confidence_range.1 = confidence_idx;
         let (sorted_lineages, _): (Vec<String>, Vec<Vec<u8>>) =
             lineage_sequence_pairs.into_iter().unzip();
+        let bloom_index = bloom_prefilter
+            .then(|| SequenceBloomTree::build(&sequence_k_mers, crate::sbt::DEFAULT_BITS_PER_LEAF));
+        let hll_index = hll_precision.map(|_| hll_registers);
         Ok(Self {
             root,
             lineages: sorted_lineages,
             sequences: sequence_map,
-            k_mer_map: k_mer_map
-                .into_par_iter()
-                .map(|seqs| seqs.into_iter().unique().sorted().collect_vec())
-                .collect(),
+            k_mer_map: k_mer_map.finalize(),
             num_tips: confidence_idx,
+            k,
+            scale,
+            bloom_index,
+            hll_precision,
+            hll_index,
+            max_ambiguity,
         })
     }
 
@@ -163,6 +299,33 @@ impl Tree {
         Ok(decoded)
     }
 
+    /// Reconstructs the `(lineage, sequence)` pairs this tree was built
+    /// from, in the same index order as `k_mer_map`'s postings. Used by
+    /// `raxtax index diff` to extend an existing database with new
+    /// reference sequences without needing to keep the original FASTA
+    /// around.
+    pub fn to_reference_records(&self) -> Vec<(String, Vec<u8>)> {
+        let mut sequences: Vec<Option<&[u8]>> = vec![None; self.num_tips];
+        for (sequence, idxs) in &self.sequences {
+            for &idx in idxs {
+                sequences[idx as usize] = Some(sequence);
+            }
+        }
+        self.lineages
+            .iter()
+            .cloned()
+            .zip(sequences)
+            .map(|(lineage, sequence)| {
+                (
+                    lineage,
+                    sequence
+                        .expect("every tip index has a sequence")
+                        .to_vec(),
+                )
+            })
+            .collect()
+    }
+
     pub fn get_shared_exact_match(&self, num_levels: usize, num_shared: usize) -> Vec<f64> {
         let mut values = vec![1.0; num_levels];
         values.push(1.0 / num_shared as f64);
@@ -1,16 +1,16 @@
 use std::{
     collections::HashSet,
     fs::File,
-    io::{BufReader, Read, Write},
+    io::{BufReader, Cursor, Read},
     path::PathBuf,
 };
 
-use anyhow::{bail, Result};
+use anyhow::Result;
+use bzip2::read::BzDecoder;
 use flate2::read::GzDecoder;
 use itertools::Itertools;
 use log::{log_enabled, warn};
-
-use crate::lineage;
+use zstd::stream::read::Decoder as ZstdDecoder;
 
 pub const F64_OUTPUT_ACCURACY: u32 = 2;
 
@@ -24,93 +24,258 @@ pub fn map_four_to_two_bit_repr(c: u8) -> Option<u16> {
     }
 }
 
-pub fn sequence_to_kmers(sequence: &[u8]) -> Vec<u16> {
+/// Expands a 4-bit IUPAC ambiguity bitmask into the concrete 2-bit base
+/// codes it represents, e.g. `R = A|G` expands to `[0b00, 0b10]`.
+pub fn expand_ambiguous_base(c: u8) -> Vec<u32> {
+    [(0b0001_u8, 0b00_u32), (0b0010, 0b01), (0b0100, 0b10), (0b1000, 0b11)]
+        .into_iter()
+        .filter_map(|(mask, code)| (c & mask != 0).then_some(code))
+        .collect()
+}
+
+/// Minimum supported k-mer length.
+pub const MIN_KMER_SIZE: usize = 8;
+/// Maximum supported k-mer length. Above this, the packed 2-bit-per-base
+/// representation no longer fits into a `u32`.
+pub const MAX_KMER_SIZE: usize = 16;
+/// Default cap on the number of concrete k-mers a single ambiguous window
+/// may expand to before it is skipped entirely.
+pub const DEFAULT_MAX_AMBIGUITY: usize = 256;
+
+/// Packs every `k`-window of `sequence` into a `2*k`-bit integer (2 bits per
+/// base, most significant base first) and returns the sorted, deduplicated
+/// set of windows. Each byte is treated as the OR of the base bitmasks it
+/// represents, so a window overlapping an IUPAC ambiguity code expands into
+/// the Cartesian product of the concrete k-mers it could mean; windows whose
+/// product exceeds `max_ambiguity` are skipped to bound the blowup.
+pub fn sequence_to_kmers(sequence: &[u8], k: usize, max_ambiguity: usize) -> Vec<u32> {
     let mut k_mers = HashSet::new();
-    sequence.windows(8).for_each(|vals| {
-        if let Some(k_mer) = vals
+    sequence.windows(k).for_each(|vals| {
+        let per_position_codes = vals
             .iter()
-            .enumerate()
-            .map(|(j, v)| map_four_to_two_bit_repr(*v).map(|c| c << (14 - j * 2)))
-            .fold_options(0_u16, |acc, c| acc | c)
-        {
-            k_mers.insert(k_mer);
+            .map(|v| expand_ambiguous_base(*v))
+            .collect_vec();
+        if per_position_codes.iter().any(Vec::is_empty) {
+            return;
+        }
+        let num_expansions: usize = per_position_codes.iter().map(Vec::len).product();
+        if num_expansions > max_ambiguity {
+            return;
         }
+        per_position_codes
+            .into_iter()
+            .enumerate()
+            .fold(vec![0_u32], |partial_k_mers, (j, codes)| {
+                let shift = 2 * (k - 1 - j);
+                partial_k_mers
+                    .iter()
+                    .cartesian_product(codes)
+                    .map(|(&acc, code)| acc | (code << shift))
+                    .collect()
+            })
+            .into_iter()
+            .for_each(|k_mer| {
+                k_mers.insert(k_mer);
+            });
     });
     k_mers.into_iter().sorted().collect_vec()
 }
 
-pub fn get_reader(path: &PathBuf) -> Result<Box<dyn Read>> {
-    let file_type = match path.extension() {
-        Some(ext) => match ext.to_str() {
-            Some(ext_str) => ext_str.to_ascii_lowercase(),
-            None => bail!("Extension could not be parsed!"),
-        },
-        None => "fasta".to_string(),
-    };
+/// Computes the reverse complement of a packed k-mer: complements each 2-bit
+/// base (A<->T, C<->G, i.e. XOR with `0b11`) and reverses the order of the
+/// `k` 2-bit groups within the word.
+pub fn reverse_complement_kmer(packed: u32, k: usize) -> u32 {
+    (0..k).fold(0_u32, |acc, j| {
+        let base = (packed >> (2 * (k - 1 - j))) & 0b11;
+        acc | ((base ^ 0b11) << (2 * j))
+    })
+}
 
-    let file = File::open(path)?;
+/// Which orientation of a query a result was classified from. Only
+/// meaningful with `--detect-strand`; without it every result is `Forward`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strand {
+    Forward,
+    ReverseComplement,
+}
 
-    match file_type.as_str() {
-        "gz" | "gzip" => {
-            let reader = Box::new(GzDecoder::new(file));
-            Ok(Box::new(BufReader::new(reader)))
+impl Strand {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Forward => "+",
+            Self::ReverseComplement => "-",
         }
-        _ => Ok(Box::new(BufReader::new(file))),
     }
 }
 
-pub fn output_results(
-    results: &[Vec<lineage::EvaluationResult<'_, '_>>],
-    mut output: Box<dyn Write>,
-) -> Result<()> {
-    let output_lines = results
+/// A `--scaled` value of 1 (the default) disables FracMinHash sketching.
+pub const DEFAULT_SCALE: u64 = 1;
+
+/// Default `--bloom-theta` present-fraction threshold for the
+/// Sequence-Bloom-Tree prefilter.
+pub const DEFAULT_BLOOM_THETA: f64 = 0.1;
+
+/// Deterministic 64-bit hash of a packed k-mer, used to decide FracMinHash
+/// sketch membership and, in [`crate::hll`], HyperLogLog register/rank
+/// selection. Uses a fixed seed so reference and query sides agree.
+pub(crate) fn hash_kmer(k_mer: u32) -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    let build_hasher = ahash::RandomState::with_seeds(0, 0, 0, 0);
+    let mut hasher = build_hasher.build_hasher();
+    hasher.write_u32(k_mer);
+    hasher.finish()
+}
+
+/// FracMinHash membership test: a k-mer is kept in the sketch iff
+/// `h(kmer) < H / scale` where `H = 2^64`. This retains roughly `1/scale` of
+/// the distinct k-mers, and unlike bottom-n MinHash, sketches built from
+/// differently sized sets remain directly comparable.
+pub fn in_scaled_sketch(k_mer: u32, scale: u64) -> bool {
+    if scale <= 1 {
+        return true;
+    }
+    let threshold = u64::MAX / scale;
+    hash_kmer(k_mer) < threshold
+}
+
+/// Number of queries read from stdin per streaming batch in `--query-file -`
+/// mode. Each batch is itself split into rayon-sized chunks by
+/// [`chunk_queries_by_residues`] and classified before the next batch is
+/// read, so results start flushing well before stdin closes and memory use
+/// stays bounded regardless of how long the upstream process keeps feeding
+/// queries.
+pub const STREAM_BATCH_QUERIES: usize = 10_000;
+
+/// Default number of roughly-equal-cost chunks each rayon worker gets handed
+/// across the whole query set, tuned empirically to keep workers busy
+/// without handing out chunks so small that scheduling overhead dominates.
+pub const DEFAULT_CHUNKS_PER_THREAD: usize = 10;
+/// Floor on chunk size for the equal-length, count-based fallback path,
+/// guarding against combinatorially tiny chunks. Residue-balanced chunking
+/// closes a chunk purely on residue budget instead, since there a single
+/// oversized query is exactly the case that should close its own chunk.
+pub const MIN_CHUNK_QUERIES: usize = 100;
+
+/// Splits `queries` into chunks (returned as their query counts, summing to
+/// `queries.len()`) balanced by total residue count rather than raw query
+/// count, so a handful of very long sequences don't starve other rayon
+/// workers the way fixed count-based chunking would: k-mer extraction and
+/// scoring cost is proportional to residues, not to query count.
+///
+/// `chunk_residues` overrides the per-chunk residue budget that would
+/// otherwise be derived from `n_threads` so each thread gets roughly
+/// [`DEFAULT_CHUNKS_PER_THREAD`] chunks. Falls back to the previous
+/// count-based chunking when every query has the same length, since
+/// residue-balancing has nothing to balance in that case.
+pub fn chunk_queries_by_residues(
+    queries: &[(String, Vec<u8>)],
+    n_threads: usize,
+    chunk_residues: Option<usize>,
+) -> Vec<usize> {
+    if queries.is_empty() {
+        return Vec::new();
+    }
+    if n_threads <= 1 {
+        return vec![queries.len()];
+    }
+    let lengths = queries.iter().map(|(_, seq)| seq.len()).collect_vec();
+    let count_based_chunk_size =
+        ((queries.len() / (n_threads * DEFAULT_CHUNKS_PER_THREAD)) + 1).max(MIN_CHUNK_QUERIES);
+    if lengths.iter().all(|&len| len == lengths[0]) {
+        return count_based_chunks(queries.len(), count_based_chunk_size);
+    }
+    let total_residues: usize = lengths.iter().sum();
+    let residue_budget = chunk_residues
+        .unwrap_or_else(|| (total_residues / (n_threads * DEFAULT_CHUNKS_PER_THREAD)).max(1));
+    let mut chunks = Vec::new();
+    let mut current_queries = 0_usize;
+    let mut current_residues = 0_usize;
+    for &len in &lengths {
+        current_queries += 1;
+        current_residues += len;
+        if current_residues >= residue_budget {
+            chunks.push(current_queries);
+            current_queries = 0;
+            current_residues = 0;
+        }
+    }
+    if current_queries > 0 {
+        chunks.push(current_queries);
+    }
+    chunks
+}
+
+fn count_based_chunks(total_queries: usize, chunk_size: usize) -> Vec<usize> {
+    let mut chunks = vec![chunk_size; total_queries / chunk_size];
+    let remainder = total_queries % chunk_size;
+    if remainder > 0 {
+        chunks.push(remainder);
+    }
+    chunks
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const BZIP2_MAGIC: [u8; 3] = [0x42, 0x5a, 0x68];
+
+/// Conventional Unix placeholder for "read from stdin instead of a file",
+/// accepted anywhere raxtax takes an input file path.
+pub const STDIN_PATH: &str = "-";
+
+/// Whether `path` is the stdin placeholder rather than an actual file path.
+pub fn is_stdin_path(path: &std::path::Path) -> bool {
+    path.as_os_str() == STDIN_PATH
+}
+
+/// Opens `path` and picks a decompressor by sniffing its magic bytes rather
+/// than trusting the file extension, so compressed inputs with unusual or
+/// missing extensions (e.g. a gzipped file with no `.gz` suffix) are still
+/// handled. The peeked bytes are fed back into the returned stream via
+/// `Cursor::chain` so no input is lost. `path` may be [`STDIN_PATH`], in
+/// which case stdin is read instead of opening a file.
+pub fn get_reader(path: &PathBuf) -> Result<Box<dyn Read>> {
+    let mut reader: Box<dyn Read> = if is_stdin_path(path) {
+        Box::new(std::io::stdin())
+    } else {
+        Box::new(File::open(path)?)
+    };
+    let mut magic = [0_u8; 4];
+    let bytes_read = reader.read(&mut magic)?;
+    let peeked = magic[..bytes_read].to_vec();
+    let chained = Cursor::new(peeked.clone()).chain(reader);
+
+    let boxed: Box<dyn Read> = if peeked.starts_with(&GZIP_MAGIC) {
+        Box::new(GzDecoder::new(chained))
+    } else if peeked.starts_with(&ZSTD_MAGIC) {
+        Box::new(ZstdDecoder::new(chained)?)
+    } else if peeked.starts_with(&BZIP2_MAGIC) {
+        Box::new(BzDecoder::new(chained))
+    } else {
+        Box::new(chained)
+    };
+    Ok(Box::new(BufReader::new(boxed)))
+}
+
+pub fn decompress_sequence(sequence: &[u8]) -> String {
+    sequence
         .iter()
-        .flat_map(|eval_results| {
-            eval_results
-                .iter()
-                .map(lineage::EvaluationResult::get_output_string)
-                .collect_vec()
+        .map(|c| match c {
+            0b0001 => 'A',
+            0b0010 => 'C',
+            0b0100 => 'G',
+            0b1000 => 'T',
+            _ => '-',
         })
-        .join("\n");
-    writeln!(output, "{}", output_lines)?;
-    Ok(())
+        .join("")
 }
 
 pub fn decompress_sequences(sequences: &[(String, Vec<u8>)]) -> Vec<String> {
     sequences
         .iter()
-        .map(|(_, s)| {
-            s.iter()
-                .map(|c| match c {
-                    0b0001 => 'A',
-                    0b0010 => 'C',
-                    0b0100 => 'G',
-                    0b1000 => 'T',
-                    _ => '-',
-                })
-                .join("")
-        })
+        .map(|(_, s)| decompress_sequence(s))
         .collect_vec()
 }
 
-pub fn output_results_tsv(
-    results: &[Vec<lineage::EvaluationResult<'_, '_>>],
-    sequences: Vec<String>,
-    mut output: Box<dyn Write>,
-) -> Result<()> {
-    let output_lines = results
-        .iter()
-        .zip_eq(sequences)
-        .flat_map(|(eval_results, sequence)| {
-            eval_results
-                .iter()
-                .map(|er| er.get_tsv_string(&sequence))
-                .collect_vec()
-        });
-    writeln!(output, "{}", output_lines.into_iter().join("\n"))?;
-    Ok(())
-}
-
 pub fn euclidean_distance_l1(a: &[f64], b: &[f64]) -> f64 {
     assert!(a.len() == b.len());
     if a.is_empty() {
@@ -159,6 +324,18 @@ pub fn report_error(e: anyhow::Error, message: impl std::fmt::Display) {
     }
 }
 
+/// Registers SIGINT/SIGTERM handlers that set a shared flag instead of
+/// terminating the process, so `raxtax` can notice a Ctrl-C between chunks,
+/// stop dispatching new work, and flush a consistent checkpoint instead of
+/// being killed mid-write. The returned flag starts `false` and is flipped
+/// to `true` at most once, by whichever of the two signals arrives first.
+pub fn install_cancellation_handler() -> Result<std::sync::Arc<std::sync::atomic::AtomicBool>> {
+    let cancelled = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGINT, std::sync::Arc::clone(&cancelled))?;
+    signal_hook::flag::register(signal_hook::consts::SIGTERM, std::sync::Arc::clone(&cancelled))?;
+    Ok(cancelled)
+}
+
 pub fn setup_threadpool_pinned(num_threads: usize) -> Result<()> {
     let cpus = get_thread_ids()?;
     if cpus.len() < num_threads {
@@ -226,7 +403,11 @@ mod tests {
 
     use crate::utils::{cosine_similarity, euclidean_distance_l1, euclidean_norm};
 
-    use super::{decompress_sequences, map_four_to_two_bit_repr, sequence_to_kmers};
+    use super::{
+        chunk_queries_by_residues, decompress_sequences, get_reader, map_four_to_two_bit_repr,
+        reverse_complement_kmer, sequence_to_kmers, DEFAULT_MAX_AMBIGUITY,
+    };
+    use std::io::{Read, Write};
 
     #[test]
     fn test_euclidean_norm() {
@@ -268,7 +449,7 @@ mod tests {
     #[test]
     fn test_sequence_to_kmers() {
         let sequence = vec![1, 2, 1, 4, 8, 2, 8, 4, 1, 4, 8, 2, 8, 4, 1, 4];
-        let kmers = sequence_to_kmers(&sequence);
+        let kmers = sequence_to_kmers(&sequence, 8, DEFAULT_MAX_AMBIGUITY);
         assert!(kmers.windows(2).all(|w| w[0] <= w[1]));
         assert_equal(
             kmers,
@@ -285,6 +466,102 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_sequence_to_kmers_wider_k() {
+        let sequence = vec![1, 2, 1, 4, 8, 2, 8, 4, 1, 4, 8, 2, 8, 4, 1, 4];
+        let kmers = sequence_to_kmers(&sequence, 10, DEFAULT_MAX_AMBIGUITY);
+        assert!(kmers.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(kmers.len(), sequence.windows(10).count());
+    }
+
+    #[test]
+    fn test_sequence_to_kmers_ambiguous() {
+        // 'N' (0b1111) at the last position expands to all 4 bases
+        let sequence = vec![1, 2, 4, 8, 1, 2, 4, 15];
+        let kmers = sequence_to_kmers(&sequence, 8, DEFAULT_MAX_AMBIGUITY);
+        assert_eq!(kmers.len(), 4);
+    }
+
+    #[test]
+    fn test_sequence_to_kmers_ambiguity_cap() {
+        // Every position ambiguous between 2 bases: 2^8 = 256 expansions, right at the cap
+        let sequence = vec![0b0011; 8];
+        assert_eq!(sequence_to_kmers(&sequence, 8, 256).len(), 256);
+        assert_eq!(sequence_to_kmers(&sequence, 8, 255).len(), 0);
+    }
+
+    #[test]
+    fn test_in_scaled_sketch() {
+        use super::in_scaled_sketch;
+        assert!((0..1000).all(|k| in_scaled_sketch(k, 1)));
+        let retained = (0..10_000).filter(|&k| in_scaled_sketch(k, 10)).count();
+        // Should retain roughly 1/10th, well within a generous margin.
+        assert!(retained > 500 && retained < 1500);
+    }
+
+    #[test]
+    fn test_reverse_complement_kmer() {
+        // A C G T A C G T -> complement each base and reverse: A C G T A C G T
+        let k_mer = 0b00_01_10_11_00_01_10_11_u32;
+        assert_eq!(reverse_complement_kmer(k_mer, 8), k_mer);
+        // A A A A A A A A -> T T T T T T T T
+        let all_a = 0_u32;
+        let all_t = 0b11_11_11_11_11_11_11_11_u32;
+        assert_eq!(reverse_complement_kmer(all_a, 8), all_t);
+        assert_eq!(reverse_complement_kmer(all_t, 8), all_a);
+    }
+
+    #[test]
+    fn test_chunk_queries_by_residues_uniform_length_falls_back_to_count_based() {
+        let queries: Vec<(String, Vec<u8>)> = (0..1000)
+            .map(|i| (i.to_string(), vec![0_u8; 50]))
+            .collect();
+        let chunks = chunk_queries_by_residues(&queries, 4, None);
+        assert_eq!(chunks.iter().sum::<usize>(), queries.len());
+        // n_threads * DEFAULT_CHUNKS_PER_THREAD = 40, so chunk size is 1000/40+1=26, floored at 100.
+        assert!(chunks.iter().all(|&size| size == 100));
+    }
+
+    #[test]
+    fn test_chunk_queries_by_residues_balances_long_sequences() {
+        let mut queries: Vec<(String, Vec<u8>)> = (0..500)
+            .map(|i| (i.to_string(), vec![0_u8; 10]))
+            .collect();
+        // A handful of very long sequences should pull their chunk's residue
+        // total up well past the short-sequence chunks' totals, closing it
+        // early instead of bundling hundreds more short queries alongside it.
+        queries.extend((0..5).map(|i| (format!("long{i}"), vec![0_u8; 100_000])));
+        let chunks = chunk_queries_by_residues(&queries, 4, None);
+        assert_eq!(chunks.iter().sum::<usize>(), queries.len());
+        // The long sequences should close a chunk well before all 505
+        // queries get bundled into one.
+        assert!(chunks.len() > 1);
+        assert!(chunks[0] < queries.len());
+        // Each long sequence alone blows the residue budget, so it must
+        // close its own chunk rather than getting bundled with most/all of
+        // the 500 short queries just to reach a query-count floor.
+        assert!(chunks.iter().all(|&size| size < 500));
+    }
+
+    #[test]
+    fn test_chunk_queries_by_residues_single_thread_is_one_chunk() {
+        let queries: Vec<(String, Vec<u8>)> =
+            (0..50).map(|i| (i.to_string(), vec![0_u8; 10])).collect();
+        assert_eq!(chunk_queries_by_residues(&queries, 1, None), vec![50]);
+    }
+
+    #[test]
+    fn test_chunk_queries_by_residues_respects_override() {
+        let queries: Vec<(String, Vec<u8>)> = (0..200)
+            .map(|i| (i.to_string(), vec![0_u8; if i % 2 == 0 { 10 } else { 1000 }]))
+            .collect();
+        let chunks = chunk_queries_by_residues(&queries, 4, Some(2020));
+        assert_eq!(chunks.iter().sum::<usize>(), queries.len());
+        // The override is honored exactly: each non-final chunk closes once
+        // its residues reach the 2020 budget, independent of query count.
+        assert!(chunks[..chunks.len() - 1].iter().all(|&size| size == 4));
+    }
+
     #[test]
     fn test_decompress_sequence() {
         let sequence = vec![(
@@ -294,4 +571,47 @@ mod tests {
         let decompressed = decompress_sequences(&sequence);
         assert_equal(decompressed, vec![String::from("ACAGTCTGAGTCTGAG")]);
     }
+
+    #[test]
+    fn test_get_reader_transparently_decompresses_gzip() {
+        let tmp =
+            std::env::temp_dir().join(format!("raxtax_get_reader_test_{}.gz", std::process::id()));
+        let mut encoder =
+            flate2::write::GzEncoder::new(std::fs::File::create(&tmp).unwrap(), flate2::Compression::default());
+        encoder.write_all(b">query\nACGT").unwrap();
+        encoder.finish().unwrap();
+        let mut contents = String::new();
+        get_reader(&tmp).unwrap().read_to_string(&mut contents).unwrap();
+        std::fs::remove_file(&tmp).unwrap();
+        assert_eq!(contents, ">query\nACGT");
+    }
+
+    #[test]
+    fn test_get_reader_transparently_decompresses_zstd() {
+        let tmp = std::env::temp_dir().join(format!(
+            "raxtax_get_reader_test_{}.zst",
+            std::process::id()
+        ));
+        let mut encoder =
+            zstd::stream::write::Encoder::new(std::fs::File::create(&tmp).unwrap(), 0).unwrap();
+        encoder.write_all(b">query\nACGT").unwrap();
+        encoder.finish().unwrap();
+        let mut contents = String::new();
+        get_reader(&tmp).unwrap().read_to_string(&mut contents).unwrap();
+        std::fs::remove_file(&tmp).unwrap();
+        assert_eq!(contents, ">query\nACGT");
+    }
+
+    #[test]
+    fn test_get_reader_passes_through_uncompressed_input() {
+        let tmp = std::env::temp_dir().join(format!(
+            "raxtax_get_reader_test_{}.fasta",
+            std::process::id()
+        ));
+        std::fs::write(&tmp, b">query\nACGT").unwrap();
+        let mut contents = String::new();
+        get_reader(&tmp).unwrap().read_to_string(&mut contents).unwrap();
+        std::fs::remove_file(&tmp).unwrap();
+        assert_eq!(contents, ">query\nACGT");
+    }
 }